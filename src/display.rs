@@ -0,0 +1,212 @@
+use crate::dsp::crunch_threshold;
+use crate::dsp::crush_multiplier;
+use crate::dsp::CrushMode;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use nih_plug::prelude::Enum;
+use nih_plug::util::db_to_gain;
+use nih_plug::util::gain_to_db;
+
+const INVERSE_ITERATIONS: usize = 32;
+
+/// Finds the parameter value in `(0, 1]` that `mapping` turns into `target`. The mappings are
+/// monotonic, but their inverse is not known in closed form, so it is found by bisection
+fn invert(mapping: fn(f32) -> f32, target: f32) -> f32 {
+    let (mut low, mut high) = (f32::EPSILON, 1_f32);
+    let increasing = mapping(high) > mapping(low);
+    for _ in 0..INVERSE_ITERATIONS {
+        let middle = (low + high) * 0.5_f32;
+        if (mapping(middle) < target) == increasing {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    (low + high) * 0.5_f32
+}
+
+/// Number of bits needed to represent the crush quantization levels between -1.0 and 1.0, or
+/// the number of mantissa bits that are kept. The codec is set by its bitrate instead
+fn crush_bits(crush: f32, mode: CrushMode) -> Option<f32> {
+    let multiplier = crush_multiplier(crush);
+    match mode {
+        CrushMode::Codec => None,
+        CrushMode::Mantissa => Some(CrushMode::mantissa_bits(multiplier) as f32),
+        _ => Some(multiplier.mul_add(2_f32, 1_f32).log2()),
+    }
+}
+
+/// Inverse of `crush_bits`
+fn crush_from_bits(bits: f32, mode: CrushMode) -> Option<f32> {
+    let multiplier = match mode {
+        CrushMode::Codec => return None,
+        // Aim for the middle of the multipliers that keep this many bits
+        CrushMode::Mantissa => 2_f32.powf(bits.round() + 1.5_f32),
+        _ => (2_f32.powf(bits) - 1_f32) * 0.5_f32,
+    };
+    Some(invert(crush_multiplier, multiplier))
+}
+
+fn percentage(string: &str) -> Option<f32> {
+    string
+        .trim_end_matches('%')
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .map(|v| v / 100_f32)
+}
+
+/// Shows crush as a percentage, or as the effective bit depth of a coefficient in the current
+/// crush mode, which `crush_mode` holds the index of
+pub fn v2s_crush(
+    bit_display: Arc<AtomicBool>,
+    crush_mode: Arc<AtomicUsize>,
+) -> Arc<dyn Fn(f32) -> String + Send + Sync> {
+    Arc::new(move |value| {
+        let mode = CrushMode::from_index(crush_mode.load(Ordering::Relaxed));
+        if !bit_display.load(Ordering::Relaxed) {
+            format!("{:.2} %", value * 100_f32)
+        } else if value == 0_f32 && mode != CrushMode::Codec {
+            "Off".to_string()
+        } else {
+            match crush_bits(value, mode) {
+                Some(bits) if mode == CrushMode::Mantissa => format!("{bits:.0} mantissa bits"),
+                Some(bits) => format!("{bits:.2} bits"),
+                None => "n/a".to_string(),
+            }
+        }
+    })
+}
+
+/// Accepts a percentage or a bit depth in the current crush mode, independently of the display
+/// mode
+pub fn s2v_crush(crush_mode: Arc<AtomicUsize>) -> Arc<dyn Fn(&str) -> Option<f32> + Send + Sync> {
+    Arc::new(move |string| {
+        let string = string.trim();
+        if string.eq_ignore_ascii_case("off") {
+            return Some(0_f32);
+        }
+        match string
+            .strip_suffix("bits")
+            .or_else(|| string.strip_suffix("bit"))
+        {
+            Some(bits) => {
+                let mode = CrushMode::from_index(crush_mode.load(Ordering::Relaxed));
+                let bits = bits.trim().trim_end_matches("mantissa").trim();
+                crush_from_bits(bits.parse::<f32>().ok()?, mode)
+            }
+            None => percentage(string),
+        }
+    })
+}
+
+/// Shows crunch as a percentage, or as the clipping level of the coefficients in dBFS
+pub fn v2s_crunch(bit_display: Arc<AtomicBool>) -> Arc<dyn Fn(f32) -> String + Send + Sync> {
+    Arc::new(move |value| {
+        if !bit_display.load(Ordering::Relaxed) {
+            format!("{:.2} %", value * 100_f32)
+        } else if value == 0_f32 {
+            "Off".to_string()
+        } else {
+            format!("{:.2} dBFS", gain_to_db(crunch_threshold(value)))
+        }
+    })
+}
+
+/// Accepts a percentage or a clipping level in dBFS, independently of the display mode
+pub fn s2v_crunch() -> Arc<dyn Fn(&str) -> Option<f32> + Send + Sync> {
+    Arc::new(|string| {
+        let string = string.trim();
+        if string.eq_ignore_ascii_case("off") {
+            return Some(0_f32);
+        }
+        match string
+            .strip_suffix("dBFS")
+            .or_else(|| string.strip_suffix("dB"))
+        {
+            Some(db) => db
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .map(|db| invert(crunch_threshold, db_to_gain(db))),
+            None => percentage(string),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: usize = 200;
+    /// Half of the last displayed digit, plus some room for the bisection
+    const TOLERANCE: f32 = 0.0051_f32;
+
+    fn values() -> impl Iterator<Item = f32> {
+        (1..=STEPS).map(|i| i as f32 / STEPS as f32)
+    }
+
+    /// Entering the displayed bit depth has to give back a value with the same bit depth
+    #[test]
+    fn crush_round_trip() {
+        let bit_display = Arc::new(AtomicBool::new(true));
+        let crush_mode = Arc::new(AtomicUsize::new(0));
+        let v2s = v2s_crush(bit_display, crush_mode.clone());
+        let s2v = s2v_crush(crush_mode.clone());
+
+        for mode in [
+            CrushMode::Uniform,
+            CrushMode::MuLaw,
+            CrushMode::Floor,
+            CrushMode::Ceil,
+            CrushMode::Stochastic,
+            CrushMode::Mantissa,
+        ] {
+            crush_mode.store(mode.to_index(), Ordering::Relaxed);
+            for value in values() {
+                let string = v2s(value);
+                let parsed = s2v(&string).unwrap_or_else(|| panic!("{mode:?}: {string}"));
+                let (bits, parsed_bits) = (
+                    crush_bits(value, mode).unwrap(),
+                    crush_bits(parsed, mode).unwrap(),
+                );
+                if mode == CrushMode::Mantissa {
+                    assert_eq!(bits, parsed_bits, "{mode:?}: {string}");
+                } else {
+                    assert!(
+                        (bits - parsed_bits).abs() < TOLERANCE,
+                        "{mode:?}: {string} parsed as {parsed_bits} bits"
+                    );
+                }
+                assert_eq!(v2s(parsed), string, "{mode:?}");
+            }
+        }
+
+        // The codec has no bit depth to enter
+        crush_mode.store(CrushMode::Codec.to_index(), Ordering::Relaxed);
+        assert_eq!(s2v(&v2s(0.5_f32)), None);
+    }
+
+    #[test]
+    fn crunch_round_trip() {
+        let v2s = v2s_crunch(Arc::new(AtomicBool::new(true)));
+        let s2v = s2v_crunch();
+
+        for value in values() {
+            let string = v2s(value);
+            let parsed = s2v(&string).unwrap_or_else(|| panic!("{string}"));
+            let (db, parsed_db) = (
+                gain_to_db(crunch_threshold(value)),
+                gain_to_db(crunch_threshold(parsed)),
+            );
+            assert!(
+                (db - parsed_db).abs() < TOLERANCE,
+                "{string} parsed as {parsed_db} dBFS"
+            );
+            assert_eq!(v2s(parsed), string);
+        }
+    }
+}
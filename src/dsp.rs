@@ -1,7 +1,10 @@
 use crate::CrunchyParams;
+use std::mem;
+use std::ops::Range;
 use std::sync::Arc;
 
 use nih_plug::plugin::ProcessStatus;
+use nih_plug::util::db_to_gain;
 
 use plugin_utils::dsp_utils::numerical_functions::quartic;
 use plugin_utils::dsp_utils::rescale_normalized_value;
@@ -9,33 +12,225 @@ use plugin_utils::dsp_utils::rescalers::ln;
 use plugin_utils::dsp_utils::rescalers::ln_reversed_unscaled_default;
 use plugin_utils::dsp_utils::ParamsBlock;
 use plugin_utils::dsp_utils::SingleChannelProcessor;
-use plugin_utils::dsp_utils::MDCT;
+
+mod auto_gain;
+
+mod blur;
+use blur::Blur;
+
+mod codec;
+use codec::band_end;
+use codec::Codec;
+
+mod cross_synthesis;
+pub use cross_synthesis::CrossSynthesis;
+
+mod crunch;
+pub use crunch::CrunchShape;
+
+mod crush;
+use crush::noise_fill;
+pub use crush::CrushMode;
+pub use crush::CrushNormalization;
+
+mod curve;
+pub use curve::CrunchCurve;
+pub use curve::CurveMode;
+pub use curve::CURVE_MAX_TILT_DB;
+pub use curve::CURVE_POINTS;
+pub use curve::CURVE_RANGE_DB;
+
+mod delay;
+
+mod dropout;
+use dropout::Dropout;
+pub use dropout::DropoutConcealment;
+pub use dropout::DropoutMode;
+
+mod engine;
+pub use engine::CrunchyEngine;
+
+mod envelope;
+use envelope::EnvelopeFollower;
+pub use envelope::EnvelopeSource;
+
+mod mangle;
+
+mod mdct;
+use mdct::Mdct;
+pub use mdct::WindowShape;
+
+mod rng;
+use rng::Rng;
+
+mod sbr;
+
+mod sparse;
+use sparse::Sparsifier;
+
+mod stutter;
+pub use stutter::StutterDivision;
+use stutter::StutterFrame;
+use stutter::MAX_STUTTER_SAMPLES;
+
+pub const MIN_BLOCK_SIZE_LOG2: i32 = 5;
+pub const MAX_BLOCK_SIZE_LOG2: i32 = 12;
+pub const DEFAULT_BLOCK_SIZE_LOG2: i32 = 6;
+pub const MAX_BLOCK_SIZE: usize = 1 << MAX_BLOCK_SIZE_LOG2;
+
+pub const DEFAULT_WINDOW_ALPHA: f32 = 4_f32;
+
+pub const MIN_BITRATE_KBPS: f32 = 8_f32;
+pub const MAX_BITRATE_KBPS: f32 = 320_f32;
+pub const DEFAULT_BITRATE_KBPS: f32 = 64_f32;
+
+pub const MIN_SPARSE_THRESHOLD_DB: f32 = -96_f32;
+
+pub const MAX_SHIFT_BINS: i32 = 128;
+
+pub const MAX_BLUR_MS: f32 = 2_000_f32;
+
+pub const MIN_AUTO_GAIN_MS: f32 = 10_f32;
+pub const MAX_AUTO_GAIN_MS: f32 = 5_000_f32;
+pub const MAX_AUTO_GAIN_CEILING_DB: f32 = 24_f32;
+
+pub const MIN_ENVELOPE_ATTACK_MS: f32 = 0.1_f32;
+pub const MAX_ENVELOPE_ATTACK_MS: f32 = 100_f32;
+pub const MIN_ENVELOPE_RELEASE_MS: f32 = 10_f32;
+pub const MAX_ENVELOPE_RELEASE_MS: f32 = 2_000_f32;
+
+pub const MIN_CUTOFF_HZ: f32 = 10_f32;
+pub const MAX_CUTOFF_HZ: f32 = 24_000_f32;
+
+/// Coarsest crush step the sidechain can ask for, as the inverse of the step
+const MIN_CROSS_SYNTHESIS_MULTIPLIER: f32 = 0.5_f32;
 
 const CRUSH_RESCALE_MIN: f32 = 0.1_f32;
 const CRUSH_RESCALE_MAX: f32 = 0.98_f32;
-const CRUSH_GAIN_A: f32 = 0.008_f32;
-const CRUSH_GAIN_B: i32 = 35;
 const CRUSH_MULTIPLIER_A: f32 = 128_f32;
 const CRUSH_MULTIPLIER_B: f32 = 2_f32;
 
-const CRUNCH_GAIN_QUARTIC_A: f32 = -106.38591_f32;
-const CRUNCH_GAIN_QUARTIC_B: f32 = 168.68996_f32;
-const CRUNCH_GAIN_QUARTIC_C: f32 = -95.357_f32;
-const CRUNCH_GAIN_QUARTIC_D: f32 = 12.67889_f32;
-const CRUNCH_GAIN_LINEAR_A: f32 = -100_f32;
-const CRUNCH_GAIN_LINEAR_B: f32 = 97_f32;
 const CRUNCH_MULTIPLIER: f32 = 0.1_f32;
 const CRUNCH_CLAMP_A: f32 = -4.99_f32;
 const CRUNCH_CLAMP_B: f32 = 5_f32;
 
+// Gain compensation in dB as a quartic of the log10 of the clipping level, fitted to pink noise
+// at -18 dBFS RMS through 64 sample blocks of the in-tree MDCT. Every crush mode has its own fit
+// in `CrushMode::gain_compensation`
+const CRUNCH_GAIN_QUARTIC_A: f32 = -1.3115_f32;
+const CRUNCH_GAIN_QUARTIC_B: f32 = -11.18272_f32;
+const CRUNCH_GAIN_QUARTIC_C: f32 = -29.22246_f32;
+const CRUNCH_GAIN_QUARTIC_D: f32 = -35.11819_f32;
+const CRUNCH_GAIN_QUARTIC_E: f32 = -14.67626_f32;
+
+/// Total latency of the processing chain in samples. `CrunchyEngine` has to collect a full
+/// block before processing it, and the MDCT overlap-add holds back one more block, which is
+/// also why the dry signal is delayed through `delay_buffer` and `mix_buffer`
+pub fn latency_samples(block_size: usize) -> u32 {
+    (block_size * 2) as u32
+}
+
+pub fn block_size_from_log2(block_size_log2: i32) -> usize {
+    1 << block_size_log2
+}
+
+fn mdct_index(block_size: usize) -> usize {
+    (block_size.trailing_zeros() as i32 - MIN_BLOCK_SIZE_LOG2) as usize
+}
+
+/// Range of the MDCT bins with center frequencies between `low_hz` and `high_hz`. A cutoff set
+/// to the end of its parameter range leaves that side of the band open
+fn bin_range(low_hz: f32, high_hz: f32, sample_rate: f32, block_size: usize) -> Range<usize> {
+    let bin_width = sample_rate / (block_size * 2) as f32;
+
+    let start = if low_hz <= MIN_CUTOFF_HZ {
+        0
+    } else {
+        ((low_hz / bin_width - 0.5_f32).ceil().max(0_f32) as usize).min(block_size)
+    };
+    let end = if high_hz >= MAX_CUTOFF_HZ {
+        block_size
+    } else {
+        ((high_hz / bin_width + 0.5_f32).floor().max(0_f32) as usize).min(block_size)
+    };
+
+    start..end.max(start)
+}
+
+/// Scale value from [0, 1] to [A, B], to remove extreme values, which either do not affect the
+/// sound, or silence it completely
+fn rescale_crush(crush: f32) -> f32 {
+    rescale_normalized_value(crush, CRUSH_RESCALE_MIN, CRUSH_RESCALE_MAX)
+}
+
+/// Inverse of the crush quantization step
+pub fn crush_multiplier(crush: f32) -> f32 {
+    // Apply a function that makes the effect ramp-up steeper
+    ln_reversed_unscaled_default(rescale_crush(crush))
+        .mul_add(CRUSH_MULTIPLIER_A, CRUSH_MULTIPLIER_B)
+}
+
+/// Level the DCT coefficients are clipped to
+pub fn crunch_threshold(crunch: f32) -> f32 {
+    // Apply a function that makes the effect ramp-up steeper. In this case we stack both ln and
+    // sqrt functions
+    let crunch = ln(crunch, 0.001).sqrt();
+
+    // Rescale crunch from [0, 1] to the desired value
+    CRUNCH_MULTIPLIER * crunch.mul_add(CRUNCH_CLAMP_A, CRUNCH_CLAMP_B)
+}
+
+/// Effect amounts that are interpolated within a frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Amounts {
+    crush: f32,
+    crunch: f32,
+    crunch_asymmetry: f32,
+}
+
+impl Amounts {
+    fn at(params_block: &CrunchyParamsBlock, index: usize) -> Self {
+        Self {
+            crush: params_block.crush[index],
+            crunch: params_block.crunch[index],
+            crunch_asymmetry: params_block.crunch_asymmetry[index],
+        }
+    }
+}
+
 pub struct CrunchySingleChannelProcessor {
-    mdct: MDCT,
+    // One transform for every selectable block size, so switching sizes does not allocate
+    mdct: Vec<Mdct>,
     block_size: usize,
 
     dct_buffer: Vec<f32>,
+    // The frame processed with the amounts of the last block
+    interpolation_buffer: Vec<f32>,
+    last_amounts: Option<Amounts>,
+    // Coefficients before crushing, for noise filling
+    crush_buffer: Vec<f32>,
+    // Transform of the sidechain and the per-bin scale derived from it
+    sidechain_mdct: Vec<Mdct>,
+    sidechain_buffer: Vec<f32>,
+    cross_synthesis_scale: Vec<f32>,
+    // Frame captured when freeze was switched on
+    frozen_buffer: Vec<f32>,
+    frozen: bool,
+    // Frames recorded for the stutter loop
+    stutter_buffer: Vec<f32>,
 
     delay_buffer: Vec<f32>,
     mix_buffer: Vec<f32>,
+
+    codec: Codec,
+    sparsifier: Sparsifier,
+    dropout: Dropout,
+    blur: Blur,
+
+    // Reseeded on reset and when the seed changes, so renders can be reproduced
+    channel: usize,
+    seed: u64,
+    rng: Rng,
 }
 
 impl SingleChannelProcessor for CrunchySingleChannelProcessor {
@@ -43,11 +238,31 @@ impl SingleChannelProcessor for CrunchySingleChannelProcessor {
 
     fn new(block_size: usize) -> Self {
         Self {
-            mdct: MDCT::new(block_size),
+            mdct: (MIN_BLOCK_SIZE_LOG2..=MAX_BLOCK_SIZE_LOG2)
+                .map(|v| Mdct::new(block_size_from_log2(v)))
+                .collect(),
             block_size,
-            dct_buffer: vec![0_f32; block_size * 2],
-            mix_buffer: vec![0_f32; block_size],
-            delay_buffer: vec![0_f32; block_size],
+            dct_buffer: vec![0_f32; MAX_BLOCK_SIZE],
+            interpolation_buffer: vec![0_f32; MAX_BLOCK_SIZE],
+            last_amounts: None,
+            crush_buffer: vec![0_f32; MAX_BLOCK_SIZE],
+            sidechain_mdct: (MIN_BLOCK_SIZE_LOG2..=MAX_BLOCK_SIZE_LOG2)
+                .map(|v| Mdct::new(block_size_from_log2(v)))
+                .collect(),
+            sidechain_buffer: vec![0_f32; MAX_BLOCK_SIZE],
+            cross_synthesis_scale: vec![1_f32; MAX_BLOCK_SIZE],
+            frozen_buffer: vec![0_f32; MAX_BLOCK_SIZE],
+            frozen: false,
+            stutter_buffer: vec![0_f32; MAX_STUTTER_SAMPLES],
+            mix_buffer: vec![0_f32; MAX_BLOCK_SIZE],
+            delay_buffer: vec![0_f32; MAX_BLOCK_SIZE],
+            codec: Codec::new(MAX_BLOCK_SIZE),
+            sparsifier: Sparsifier::new(MAX_BLOCK_SIZE),
+            dropout: Dropout::new(MAX_BLOCK_SIZE),
+            blur: Blur::new(MAX_BLOCK_SIZE),
+            channel: 0,
+            seed: 0,
+            rng: Rng::new(0),
         }
     }
 
@@ -57,88 +272,172 @@ impl SingleChannelProcessor for CrunchySingleChannelProcessor {
         output: &mut [f32],
         params_block: &Self::ParamsBlock,
     ) -> nih_plug::prelude::ProcessStatus {
+        self.process_wet(block, output, params_block);
+        self.mix(output, params_block);
+
+        ProcessStatus::Normal
+    }
+}
+
+impl CrunchySingleChannelProcessor {
+    /// Runs `block` through the whole chain into `output`, without mixing in the dry signal.
+    /// `CrunchyEngine` levels all channels with one shared gain before calling `mix`
+    pub fn process_wet(
+        &mut self,
+        block: &[f32],
+        output: &mut [f32],
+        params_block: &CrunchyParamsBlock,
+    ) {
         let len: usize = block.len();
+        let block_size = self.block_size;
+
+        if params_block.seed != self.seed {
+            self.seed = params_block.seed;
+            self.reseed();
+        }
+
+        let index = mdct_index(block_size);
+
         // Clone block for mix
-        self.delay_buffer.copy_from_slice(block);
+        self.delay_buffer[..len].copy_from_slice(block);
         // Apply drive
         for i in 0..len {
             output[i] = block[i] * params_block.drive[i];
         }
 
-        self.mdct.mdct(output, self.dct_buffer.as_mut_slice());
-
-        let mut gain_compensation = 1_f32;
-
-        // Apply crush effect. Bitcrushes DCT coefficients
-        let crush = params_block.crush[self.block_size / 2];
-        if crush != 0_f32 {
-            // Scale value from [0, 1] to [A, B], to remove extreme values, which either do
-            // not affect the sound, or silence it completely
-            let crush = rescale_normalized_value(crush, CRUSH_RESCALE_MIN, CRUSH_RESCALE_MAX);
+        self.mdct[index].mdct(output, &mut self.dct_buffer[..block_size]);
+        let dct_buffer = &mut self.dct_buffer[..block_size];
 
-            // Calculate gain compensation
-            gain_compensation = if crush > 0.85_f32 {
-                (crush + CRUSH_GAIN_A).powi(CRUSH_GAIN_B).exp()
+        // Capture the first frame after freeze is switched on and keep resynthesizing it. The
+        // effects below still apply to the frozen frame
+        if params_block.freeze {
+            if self.frozen {
+                dct_buffer.copy_from_slice(&self.frozen_buffer[..block_size]);
             } else {
-                1_f32
-            };
-
-            // Apply a function that makes the effect ramp-up steeper
-            let crush = ln_reversed_unscaled_default(crush);
-            let crush_multiplier = crush.mul_add(CRUSH_MULTIPLIER_A, CRUSH_MULTIPLIER_B);
+                self.frozen_buffer[..block_size].copy_from_slice(dct_buffer);
+                self.frozen = true;
+            }
+        } else {
+            self.frozen = false;
+        }
 
-            // Bitcrush all DCT coefficients
-            for i in 0..self.block_size * 2 {
-                self.dct_buffer[i] =
-                    (self.dct_buffer[i] * crush_multiplier).round() / crush_multiplier;
+        // Record or loop frames for the stutter, before the effects so they are applied live
+        match params_block.stutter_frame {
+            StutterFrame::Off => (),
+            StutterFrame::Record(frame) => {
+                let start = frame * block_size;
+                self.stutter_buffer[start..start + block_size].copy_from_slice(dct_buffer);
+            }
+            StutterFrame::Play(frame) => {
+                let start = frame * block_size;
+                dct_buffer.copy_from_slice(&self.stutter_buffer[start..start + block_size]);
             }
         }
 
-        // Apply crunch effect. Clips the DCT coefficients
-        let crunch = params_block.crunch[self.block_size / 2];
-        if crunch != 0_f32 {
-            // Calculate gain compensation
-            gain_compensation *= 0.1_f32.powf(
-                (quartic(
-                    crunch,
-                    CRUNCH_GAIN_QUARTIC_A,
-                    CRUNCH_GAIN_QUARTIC_B,
-                    CRUNCH_GAIN_QUARTIC_C,
-                    CRUNCH_GAIN_QUARTIC_D,
-                    if crunch > 0.97 {
-                        crunch.mul_add(CRUNCH_GAIN_LINEAR_A, CRUNCH_GAIN_LINEAR_B)
-                    } else {
-                        0_f32
-                    },
-                )) * 0.05_f32,
+        // Move and shuffle the bins before they are processed
+        mangle::shift(dct_buffer, params_block.shift);
+        if params_block.scramble != 0_f32 {
+            let scramble_bins = bin_range(
+                params_block.scramble_low,
+                params_block.scramble_high,
+                params_block.sample_rate,
+                block_size,
+            );
+            mangle::scramble(
+                &mut dct_buffer[scramble_bins],
+                params_block.scramble,
+                params_block.seed,
             );
+        }
 
-            // Apply a function that makes the effect ramp-up steeper. In this case we
-            // stack both ln and sqrt functions
-            let crunch = ln(crunch, 0.001).sqrt();
+        // Crunch and crush follow their automation within a frame by crossfading between the
+        // frame processed with the amounts of the last block and with those of this one
+        let end = Amounts::at(params_block, block_size / 2);
+        let start = self.last_amounts.unwrap_or(end);
+        self.last_amounts = Some(end);
 
-            // Rescale crunch from [0, 1] to the desired value
-            let crunch_clamp = crunch.mul_add(CRUNCH_CLAMP_A, CRUNCH_CLAMP_B);
+        let mut coefficients = mem::take(&mut self.dct_buffer);
+        if start == end {
+            self.crush_and_crunch(&mut coefficients[..block_size], end, params_block);
+        } else {
+            let mut start_coefficients = mem::take(&mut self.interpolation_buffer);
+            start_coefficients[..block_size].copy_from_slice(&coefficients[..block_size]);
+            self.crush_and_crunch(&mut start_coefficients[..block_size], start, params_block);
+            self.crush_and_crunch(&mut coefficients[..block_size], end, params_block);
 
-            // Clamp DCT coefficients
-            for i in 0..self.block_size * 2 {
-                self.dct_buffer[i] = self.dct_buffer[i].clamp(
-                    -CRUNCH_MULTIPLIER * crunch_clamp,
-                    CRUNCH_MULTIPLIER * crunch_clamp,
-                );
+            for (end, start) in coefficients[..block_size]
+                .iter_mut()
+                .zip(start_coefficients.iter())
+            {
+                *end -= start;
+            }
+            self.mdct[index].ramp(&mut coefficients[..block_size]);
+            for (end, start) in coefficients[..block_size]
+                .iter_mut()
+                .zip(start_coefficients.iter())
+            {
+                *end += start;
             }
+
+            self.interpolation_buffer = start_coefficients;
         }
+        self.dct_buffer = coefficients;
+        let dct_buffer = &mut self.dct_buffer[..block_size];
 
-        self.mdct.imdct(self.dct_buffer.as_mut_slice(), output);
+        // Keep only the loudest coefficients, at least one of them. The count is a fraction of
+        // the bins, so it means the same at every block size. The lowest threshold only counts
+        // as disabled while every bin is kept
+        let sparse_count = ((params_block.sparse_fraction * block_size as f32).ceil() as usize)
+            .clamp(1, block_size);
+        if sparse_count < block_size
+            || params_block.sparse_threshold > db_to_gain(MIN_SPARSE_THRESHOLD_DB)
+        {
+            self.sparsifier
+                .process(dct_buffer, sparse_count, params_block.sparse_threshold);
+        }
 
-        // Apply gain correction
-        if gain_compensation != 1_f32 {
-            for i in 0..len {
-                output[i] *= gain_compensation;
-            }
+        // Smear the magnitudes over time
+        self.blur.process(
+            dct_buffer,
+            params_block.blur[block_size / 2] * 0.001_f32,
+            block_size as f32 / params_block.sample_rate,
+        );
+
+        // Rebuild everything above the crossover from the bins below it
+        if params_block.sbr_crossover < MAX_CUTOFF_HZ {
+            let crossover = bin_range(
+                params_block.sbr_crossover,
+                MAX_CUTOFF_HZ,
+                params_block.sample_rate,
+                block_size,
+            )
+            .start;
+            sbr::replicate(
+                dct_buffer,
+                crossover,
+                params_block.sample_rate / (block_size * 2) as f32,
+                params_block.sbr_gain[block_size / 2],
+            );
         }
 
-        // Apply mix and gain
+        // Lose coefficients like a lossy network would
+        self.dropout.process(
+            dct_buffer,
+            &params_block.dropout_lost[..block_size],
+            params_block.dropout_concealment,
+        );
+
+        self.mdct[index].imdct(dct_buffer, output);
+    }
+
+    /// The dry signal that goes with the output of the last `process_wet`, delayed by as much
+    pub fn dry(&self) -> &[f32] {
+        &self.mix_buffer[..self.block_size]
+    }
+
+    /// Mixes the dry signal into the output of `process_wet` and applies the output gain
+    pub fn mix(&mut self, output: &mut [f32], params_block: &CrunchyParamsBlock) {
+        let len = output.len();
         for i in 0..len {
             output[i] = output[i].mul_add(
                 params_block.mix[i],
@@ -146,9 +445,243 @@ impl SingleChannelProcessor for CrunchySingleChannelProcessor {
             ) * params_block.gain[i];
         }
 
-        self.mix_buffer.copy_from_slice(&self.delay_buffer);
+        self.mix_buffer[..len].copy_from_slice(&self.delay_buffer[..len]);
+    }
 
-        ProcessStatus::Normal
+    /// Crushes and crunches the coefficients of a frame with the given amounts, including the
+    /// gain compensation of both
+    fn crush_and_crunch(
+        &mut self,
+        coefficients: &mut [f32],
+        amounts: Amounts,
+        params_block: &CrunchyParamsBlock,
+    ) {
+        let block_size = coefficients.len();
+        let mut crush_gain = 1_f32;
+        let mut crunch_gain = 1_f32;
+        let crush_bins = bin_range(
+            params_block.crush_low,
+            params_block.crush_high,
+            params_block.sample_rate,
+            block_size,
+        );
+        let crunch_bins = bin_range(
+            params_block.crunch_low,
+            params_block.crunch_high,
+            params_block.sample_rate,
+            block_size,
+        );
+
+        // Apply crush effect. Bitcrushes DCT coefficients. The codec is set by its bitrate, so it
+        // runs whatever the crush amount. It spreads its bits over the bands by itself, so it
+        // ignores normalization and crush cross-synthesis
+        let crush = amounts.crush;
+        let mode = params_block.crush_mode;
+        if crush != 0_f32 || mode == CrushMode::Codec {
+            let crush_multiplier = crush_multiplier(crush);
+
+            // Calculate gain compensation. Normalized bands always keep their loudest
+            // coefficients, so they do not lose level the way raw coefficients do, and auto gain
+            // measures the level instead
+            let normalization = if mode == CrushMode::Codec {
+                CrushNormalization::Off
+            } else {
+                params_block.crush_normalization
+            };
+            if normalization == CrushNormalization::Off && !params_block.auto_gain {
+                crush_gain = mode.gain_compensation(crush_multiplier, params_block.bitrate);
+            }
+
+            let noise_fill_amount = params_block.noise_fill[block_size / 2];
+            if noise_fill_amount != 0_f32 {
+                self.crush_buffer[crush_bins.clone()]
+                    .copy_from_slice(&coefficients[crush_bins.clone()]);
+            }
+
+            // Bitcrush DCT coefficients in the selected band
+            if mode == CrushMode::Codec {
+                // The budget is shared by all channels
+                let bit_budget = params_block.bitrate * 1_000_f32 * block_size as f32
+                    / params_block.sample_rate
+                    / params_block.channels as f32;
+                self.codec.process(
+                    &mut coefficients[crush_bins.clone()],
+                    crush_bins.start,
+                    params_block.sample_rate / (block_size * 2) as f32,
+                    bit_budget,
+                );
+            } else if normalization == CrushNormalization::Off
+                && params_block.cross_synthesis != CrossSynthesis::Crush
+            {
+                mode.apply(
+                    &mut coefficients[crush_bins.clone()],
+                    crush_multiplier,
+                    &mut self.rng,
+                );
+            } else {
+                // Quantize every scale-factor band relative to its own level, so the result
+                // does not depend on the input level, and with a step set by the sidechain
+                let bin_width = params_block.sample_rate / (block_size * 2) as f32;
+                let mut start = crush_bins.start;
+                while start < crush_bins.end {
+                    let end = band_end(start, bin_width, crush_bins.end);
+                    let band = &mut coefficients[start..end];
+                    let multiplier = if params_block.cross_synthesis == CrossSynthesis::Crush {
+                        (crush_multiplier * self.cross_synthesis_scale[start])
+                            .max(MIN_CROSS_SYNTHESIS_MULTIPLIER)
+                    } else {
+                        crush_multiplier
+                    };
+                    if normalization == CrushNormalization::Off {
+                        mode.apply(band, multiplier, &mut self.rng);
+                    } else if let Some(scale) = normalization.scale(band) {
+                        band.iter_mut().for_each(|v| *v /= scale);
+                        mode.apply(band, multiplier, &mut self.rng);
+                        band.iter_mut().for_each(|v| *v *= scale);
+                    }
+                    start = end;
+                }
+            }
+
+            // Substitute noise for the coefficients that were quantized to zero, band by band
+            // so the noise follows the spectral envelope
+            if noise_fill_amount != 0_f32 {
+                let bin_width = params_block.sample_rate / (block_size * 2) as f32;
+                let mut start = crush_bins.start;
+                while start < crush_bins.end {
+                    let end = band_end(start, bin_width, crush_bins.end);
+                    noise_fill(
+                        &self.crush_buffer[start..end],
+                        &mut coefficients[start..end],
+                        noise_fill_amount,
+                        crush_gain,
+                        &mut self.rng,
+                    );
+                    start = end;
+                }
+            }
+        }
+
+        // Apply crunch effect. Clips the DCT coefficients
+        let crunch = amounts.crunch;
+        if crunch != 0_f32 {
+            let threshold = crunch_threshold(crunch);
+
+            // Calculate gain compensation, unless auto gain measures it. Clipping only removes
+            // level, so the compensation never cuts
+            if !params_block.auto_gain {
+                crunch_gain = db_to_gain(
+                    quartic(
+                        threshold.log10(),
+                        CRUNCH_GAIN_QUARTIC_A,
+                        CRUNCH_GAIN_QUARTIC_B,
+                        CRUNCH_GAIN_QUARTIC_C,
+                        CRUNCH_GAIN_QUARTIC_D,
+                        CRUNCH_GAIN_QUARTIC_E,
+                    )
+                    .max(0_f32),
+                );
+            }
+
+            // Asymmetry moves the threshold of one polarity up and the other one down
+            let asymmetry = amounts.crunch_asymmetry;
+            let positive = threshold * (1_f32 + asymmetry);
+            let negative = threshold * (1_f32 - asymmetry);
+
+            // Clip DCT coefficients in the selected band, shaped by the threshold curve and
+            // optionally by the sidechain
+            let shape = params_block.crunch_shape;
+            let sidechain = params_block.cross_synthesis == CrossSynthesis::Crunch;
+            for ((coefficient, curve), sidechain_scale) in coefficients[crunch_bins.clone()]
+                .iter_mut()
+                .zip(params_block.crunch_curve[crunch_bins.clone()].iter())
+                .zip(self.cross_synthesis_scale[crunch_bins.clone()].iter())
+            {
+                let scale = if sidechain {
+                    curve * sidechain_scale
+                } else {
+                    *curve
+                };
+                *coefficient = shape.apply(*coefficient, positive * scale, negative * scale);
+            }
+        }
+
+        // Apply gain correction. The transform is linear, so correcting the coefficients only
+        // affects the bands that were processed
+        if crush_gain != 1_f32 {
+            for coefficient in coefficients[crush_bins].iter_mut() {
+                *coefficient *= crush_gain;
+            }
+        }
+        if crunch_gain != 1_f32 {
+            for coefficient in coefficients[crunch_bins].iter_mut() {
+                *coefficient *= crunch_gain;
+            }
+        }
+    }
+
+    /// Switches to one of the preallocated transform sizes and sets the window of its
+    /// transforms. The state of the new transform is cleared, so nothing recorded the last time
+    /// it was in use leaks into the output. `CrunchyEngine` then warms it up with the last blocks
+    /// of the input
+    pub fn set_transform(&mut self, block_size: usize, window: WindowShape, window_alpha: f32) {
+        self.block_size = block_size;
+        let index = mdct_index(block_size);
+        self.mdct[index].set_window(window, window_alpha);
+        self.sidechain_mdct[index].set_window(window, window_alpha);
+        self.reset();
+    }
+
+    /// Sets the channel index the random generator is seeded with
+    pub fn set_channel(&mut self, channel: usize) {
+        self.channel = channel;
+        self.reseed();
+    }
+
+    /// Transforms the sidechain block that goes with the next call to `process`. The sidechain
+    /// is only transformed while cross-synthesis is enabled, so the first frame after enabling it
+    /// still holds an old block
+    pub fn process_sidechain(&mut self, block: &[f32], params_block: &CrunchyParamsBlock) {
+        let block_size = self.block_size;
+        if params_block.cross_synthesis == CrossSynthesis::Off {
+            return;
+        }
+
+        self.sidechain_mdct[mdct_index(block_size)]
+            .mdct(block, &mut self.sidechain_buffer[..block_size]);
+
+        cross_synthesis::sidechain_scale(
+            &self.sidechain_buffer[..block_size],
+            &mut self.cross_synthesis_scale,
+            params_block.cross_synthesis_amount,
+            params_block.sample_rate / (block_size * 2) as f32,
+        );
+    }
+
+    /// Restarts the random sequence, which depends on both the seed and the channel
+    fn reseed(&mut self) {
+        self.rng = Rng::new((self.seed << 16) | self.channel as u64);
+    }
+
+    /// Clears the overlap and delay state of the current block size
+    pub fn reset(&mut self) {
+        self.mdct[mdct_index(self.block_size)].reset();
+        self.sidechain_mdct[mdct_index(self.block_size)].reset();
+        self.reseed();
+        self.dropout.reset();
+        self.blur.reset();
+
+        self.dct_buffer.fill(0_f32);
+        self.interpolation_buffer.fill(0_f32);
+        self.last_amounts = None;
+        self.crush_buffer.fill(0_f32);
+        self.sidechain_buffer.fill(0_f32);
+        self.cross_synthesis_scale.fill(1_f32);
+        self.frozen_buffer.fill(0_f32);
+        self.frozen = false;
+        self.stutter_buffer.fill(0_f32);
+        self.delay_buffer.fill(0_f32);
+        self.mix_buffer.fill(0_f32);
     }
 }
 
@@ -159,8 +692,117 @@ pub struct CrunchyParamsBlock {
     pub drive: Vec<f32>,
     pub crunch: Vec<f32>,
     pub crush: Vec<f32>,
+    pub crunch_asymmetry: Vec<f32>,
+    pub noise_fill: Vec<f32>,
+    pub sbr_gain: Vec<f32>,
+    pub blur: Vec<f32>,
     pub mix: Vec<f32>,
     pub gain: Vec<f32>,
+
+    pub crunch_shape: CrunchShape,
+    pub crush_mode: CrushMode,
+    pub crush_normalization: CrushNormalization,
+    pub bitrate: f32,
+    pub sparse_fraction: f32,
+    pub sparse_threshold: f32,
+    pub freeze: bool,
+    pub dropout_concealment: DropoutConcealment,
+    pub seed: u64,
+    pub auto_gain: bool,
+    pub cross_synthesis: CrossSynthesis,
+    pub cross_synthesis_amount: f32,
+    pub auto_gain_time: f32,
+    pub auto_gain_ceiling: f32,
+    pub shift: i32,
+    pub scramble: f32,
+    /// Set by `CrunchyEngine` for every block
+    pub stutter_frame: StutterFrame,
+    /// Set by `CrunchyEngine` for every block, one flag per bin
+    pub dropout_lost: Vec<bool>,
+
+    pub sample_rate: f32,
+    pub channels: usize,
+    pub crunch_low: f32,
+    pub crunch_high: f32,
+    pub crush_low: f32,
+    pub crush_high: f32,
+    pub scramble_low: f32,
+    pub scramble_high: f32,
+    pub sbr_crossover: f32,
+
+    /// Crunch threshold multiplier for every bin
+    pub crunch_curve: Vec<f32>,
+    curve: CrunchCurve,
+
+    /// Envelope of the dry input for every sample, written by `CrunchyEngine` before the
+    /// parameters are read
+    pub envelope: Vec<f32>,
+    // Envelope of the block collected before, which lines up with the output mix is applied to
+    last_envelope: Vec<f32>,
+    envelope_follower: EnvelopeFollower,
+}
+
+impl CrunchyParamsBlock {
+    /// Snaps all smoothers to their target values
+    pub fn reset(&mut self) {
+        self.params.drive.smoothed.reset(self.params.drive.value());
+        self.params
+            .crunch
+            .smoothed
+            .reset(self.params.crunch.value());
+        self.params.crush.smoothed.reset(self.params.crush.value());
+        self.params
+            .crunch_asymmetry
+            .smoothed
+            .reset(self.params.crunch_asymmetry.value());
+        self.params
+            .noise_fill
+            .smoothed
+            .reset(self.params.noise_fill.value());
+        self.params
+            .sbr_gain
+            .smoothed
+            .reset(self.params.sbr_gain.value());
+        self.params.blur.smoothed.reset(self.params.blur.value());
+        self.params.mix.smoothed.reset(self.params.mix.value());
+        self.params.gain.smoothed.reset(self.params.gain.value());
+        self.envelope_follower.reset();
+        self.envelope.fill(0_f32);
+        self.last_envelope.fill(0_f32);
+    }
+
+    /// Switches to a new block size. The values of the last block are held at their last value
+    /// where the new block is longer, so the new size can be warmed up with them
+    pub fn set_block_size(&mut self, block_size: usize) {
+        if block_size > self.block_size {
+            let old_block_size = self.block_size;
+            for values in [
+                &mut self.drive,
+                &mut self.crunch,
+                &mut self.crush,
+                &mut self.crunch_asymmetry,
+                &mut self.noise_fill,
+                &mut self.sbr_gain,
+                &mut self.blur,
+                &mut self.mix,
+                &mut self.gain,
+                &mut self.last_envelope,
+            ] {
+                let last = values[old_block_size - 1];
+                values[old_block_size..block_size].fill(last);
+            }
+        }
+        self.block_size = block_size;
+        self.update_crunch_curve();
+    }
+
+    /// Samples the threshold curve at the center of every bin of the current block size
+    fn update_crunch_curve(&mut self) {
+        let bin_width = self.sample_rate / (self.block_size * 2) as f32;
+        for (i, scale) in self.crunch_curve[..self.block_size].iter_mut().enumerate() {
+            *scale = db_to_gain(self.curve.gain_db_at((i as f32 + 0.5_f32) * bin_width));
+        }
+    }
 }
 
 impl ParamsBlock for CrunchyParamsBlock {
@@ -169,11 +811,47 @@ impl ParamsBlock for CrunchyParamsBlock {
         Self {
             params,
             block_size,
-            drive: vec![0_f32; block_size],
-            crunch: vec![0_f32; block_size],
-            crush: vec![0_f32; block_size],
-            mix: vec![0_f32; block_size],
-            gain: vec![0_f32; block_size],
+            drive: vec![0_f32; MAX_BLOCK_SIZE],
+            crunch: vec![0_f32; MAX_BLOCK_SIZE],
+            crush: vec![0_f32; MAX_BLOCK_SIZE],
+            crunch_asymmetry: vec![0_f32; MAX_BLOCK_SIZE],
+            noise_fill: vec![0_f32; MAX_BLOCK_SIZE],
+            sbr_gain: vec![0_f32; MAX_BLOCK_SIZE],
+            blur: vec![0_f32; MAX_BLOCK_SIZE],
+            mix: vec![0_f32; MAX_BLOCK_SIZE],
+            gain: vec![0_f32; MAX_BLOCK_SIZE],
+            crunch_shape: CrunchShape::Hard,
+            crush_mode: CrushMode::Uniform,
+            crush_normalization: CrushNormalization::Off,
+            bitrate: DEFAULT_BITRATE_KBPS,
+            sparse_fraction: 1_f32,
+            sparse_threshold: 0_f32,
+            freeze: false,
+            dropout_concealment: DropoutConcealment::Mute,
+            seed: 0,
+            auto_gain: false,
+            cross_synthesis: CrossSynthesis::Off,
+            cross_synthesis_amount: 0_f32,
+            auto_gain_time: 0_f32,
+            auto_gain_ceiling: 0_f32,
+            shift: 0,
+            scramble: 0_f32,
+            stutter_frame: StutterFrame::Off,
+            dropout_lost: vec![false; MAX_BLOCK_SIZE],
+            sample_rate: 44_100_f32,
+            channels: 1,
+            crunch_low: MIN_CUTOFF_HZ,
+            crunch_high: MAX_CUTOFF_HZ,
+            crush_low: MIN_CUTOFF_HZ,
+            crush_high: MAX_CUTOFF_HZ,
+            scramble_low: MIN_CUTOFF_HZ,
+            scramble_high: MAX_CUTOFF_HZ,
+            sbr_crossover: MAX_CUTOFF_HZ,
+            crunch_curve: vec![1_f32; MAX_BLOCK_SIZE],
+            curve: CrunchCurve::default(),
+            envelope: vec![0_f32; MAX_BLOCK_SIZE],
+            last_envelope: vec![0_f32; MAX_BLOCK_SIZE],
+            envelope_follower: EnvelopeFollower::default(),
         }
     }
 
@@ -190,6 +868,22 @@ impl ParamsBlock for CrunchyParamsBlock {
             .crush
             .smoothed
             .next_block(self.crush.as_mut_slice(), self.block_size);
+        self.params
+            .crunch_asymmetry
+            .smoothed
+            .next_block(self.crunch_asymmetry.as_mut_slice(), self.block_size);
+        self.params
+            .noise_fill
+            .smoothed
+            .next_block(self.noise_fill.as_mut_slice(), self.block_size);
+        self.params
+            .sbr_gain
+            .smoothed
+            .next_block(self.sbr_gain.as_mut_slice(), self.block_size);
+        self.params
+            .blur
+            .smoothed
+            .next_block(self.blur.as_mut_slice(), self.block_size);
         self.params
             .mix
             .smoothed
@@ -198,5 +892,131 @@ impl ParamsBlock for CrunchyParamsBlock {
             .gain
             .smoothed
             .next_block(self.gain.as_mut_slice(), self.block_size);
+
+        self.crunch_shape = self.params.crunch_shape.value();
+        self.crush_mode = self.params.crush_mode.value();
+        self.crush_normalization = self.params.crush_normalization.value();
+        self.bitrate = self.params.bitrate.value();
+        self.sparse_fraction = self.params.sparse_fraction.value();
+        self.sparse_threshold = self.params.sparse_threshold.value();
+        self.freeze = self.params.freeze.value();
+        self.dropout_concealment = self.params.dropout_concealment.value();
+        self.seed = self.params.seed.value() as u64;
+        self.auto_gain = self.params.auto_gain.value();
+        self.cross_synthesis = self.params.cross_synthesis.value();
+        self.cross_synthesis_amount = self.params.cross_synthesis_amount.value();
+        self.auto_gain_time = self.params.auto_gain_time.value();
+        self.auto_gain_ceiling = self.params.auto_gain_ceiling.value();
+        self.shift = self.params.shift.value();
+        self.scramble = self.params.scramble.value();
+        self.crunch_low = self.params.crunch_low.value();
+        self.crunch_high = self.params.crunch_high.value();
+        self.crush_low = self.params.crush_low.value();
+        self.crush_high = self.params.crush_high.value();
+        self.scramble_low = self.params.scramble_low.value();
+        self.scramble_high = self.params.scramble_high.value();
+        self.sbr_crossover = self.params.sbr_crossover.value();
+
+        // The editor might be holding the lock, in which case the last curve is used
+        if let Ok(curve) = self.params.crunch_curve.try_read() {
+            self.curve = *curve;
+        }
+        self.update_crunch_curve();
+
+        // Turn the input peaks into an envelope and add it to the modulated amounts. Crunch and
+        // crush reach the amounts of a block in the middle of its frame, which is where the
+        // output lines up with it. Mix applies to the output of the frame before, which is
+        // played back a block later, so it follows the envelope one block late
+        let attack =
+            EnvelopeFollower::coefficient(self.params.envelope_attack.value(), self.sample_rate);
+        let release =
+            EnvelopeFollower::coefficient(self.params.envelope_release.value(), self.sample_rate);
+        for value in self.envelope[..self.block_size].iter_mut() {
+            *value = self
+                .envelope_follower
+                .next(*value, attack, release)
+                .min(1_f32);
+        }
+        for (values, depth, envelope) in [
+            (
+                &mut self.crunch,
+                self.params.envelope_crunch.value(),
+                &self.envelope,
+            ),
+            (
+                &mut self.crush,
+                self.params.envelope_crush.value(),
+                &self.envelope,
+            ),
+            (
+                &mut self.mix,
+                self.params.envelope_mix.value(),
+                &self.last_envelope,
+            ),
+        ] {
+            if depth != 0_f32 {
+                for (value, envelope) in values[..self.block_size].iter_mut().zip(envelope.iter()) {
+                    *value = depth.mul_add(*envelope, *value).clamp(0_f32, 1_f32);
+                }
+            }
+        }
+        mem::swap(&mut self.envelope, &mut self.last_envelope);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nih_plug::prelude::FloatParam;
+    use nih_plug::prelude::FloatRange;
+
+    const BLOCK_SIZE: usize = 64;
+
+    fn amount(name: &str, value: f32) -> FloatParam {
+        FloatParam::new(
+            name,
+            value,
+            FloatRange::Linear {
+                min: 0_f32,
+                max: 1_f32,
+            },
+        )
+    }
+
+    /// A step in the input has to reach crunch and crush with the block it arrives in, and mix
+    /// with the block after, when its frame is played back
+    #[test]
+    fn envelope_follows_step() {
+        let params = Arc::new(CrunchyParams {
+            crunch: amount("Crunch", 0_f32),
+            crush: amount("Crush", 0_f32),
+            mix: amount("Mix", 0_f32),
+            envelope_attack: amount("Envelope attack", MIN_ENVELOPE_ATTACK_MS),
+            envelope_crunch: amount("Envelope to crunch", 1_f32),
+            envelope_crush: amount("Envelope to crush", 1_f32),
+            envelope_mix: amount("Envelope to mix", 1_f32),
+            ..CrunchyParams::default()
+        });
+        let mut params_block = CrunchyParamsBlock::new(params, BLOCK_SIZE);
+        params_block.reset();
+
+        let mut next_block = |peak: f32| {
+            params_block.envelope[..BLOCK_SIZE].fill(peak);
+            params_block.from_params();
+            let last = BLOCK_SIZE - 1;
+            (
+                params_block.crunch[last],
+                params_block.crush[last],
+                params_block.mix[last],
+            )
+        };
+
+        assert_eq!(next_block(0_f32), (0_f32, 0_f32, 0_f32));
+        let (crunch, crush, mix) = next_block(1_f32);
+        assert!(crunch > 0.99_f32 && crush > 0.99_f32, "{crunch} {crush}");
+        assert_eq!(mix, 0_f32);
+        let (_, _, mix) = next_block(1_f32);
+        assert!(mix > 0.99_f32, "{mix}");
     }
 }
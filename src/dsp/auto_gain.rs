@@ -0,0 +1,81 @@
+use nih_plug::util::db_to_gain;
+
+/// Power below which a signal counts as silent, the gain is held while either side is silent
+const SILENCE_POWER: f32 = 1e-10_f32;
+
+/// Mean square of all samples of all channels
+fn mean_power<'a>(channels: impl Iterator<Item = &'a [f32]>) -> f32 {
+    let (sum, count) = channels.fold((0_f32, 0), |(sum, count), samples| {
+        (
+            sum + samples.iter().map(|v| v * v).sum::<f32>(),
+            count + samples.len(),
+        )
+    });
+    sum / count.max(1) as f32
+}
+
+/// Matches the loudness of the processed signal to the dry one. Both are measured as mean
+/// square power smoothed over blocks, and the gain is ramped within a block to avoid zipper
+/// noise. The power is measured across all channels, so they all get the same gain and the
+/// stereo image is kept
+pub struct AutoGain {
+    dry_power: f32,
+    wet_power: f32,
+    last_gain: f32,
+    gain: f32,
+}
+
+impl Default for AutoGain {
+    fn default() -> Self {
+        Self {
+            dry_power: 0_f32,
+            wet_power: 0_f32,
+            last_gain: 1_f32,
+            gain: 1_f32,
+        }
+    }
+}
+
+impl AutoGain {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Measures one block of every channel. `dry` and `wet` yield the channels, `len` is the
+    /// block size, `time` the time constant of the measurement in seconds and `ceiling_db` the
+    /// most the gain can boost
+    pub fn update<'a>(
+        &mut self,
+        dry: impl Iterator<Item = &'a [f32]>,
+        wet: impl Iterator<Item = &'a [f32]>,
+        len: usize,
+        time: f32,
+        ceiling_db: f32,
+        sample_rate: f32,
+    ) {
+        let feedback = (-(len as f32 / sample_rate) / time).exp();
+        let (dry_power, wet_power) = (mean_power(dry), mean_power(wet));
+        self.dry_power = dry_power + (self.dry_power - dry_power) * feedback;
+        self.wet_power = wet_power + (self.wet_power - wet_power) * feedback;
+
+        self.last_gain = self.gain;
+        if self.dry_power > SILENCE_POWER && self.wet_power > SILENCE_POWER {
+            self.gain = (self.dry_power / self.wet_power)
+                .sqrt()
+                .min(db_to_gain(ceiling_db));
+        }
+    }
+
+    /// The gain reached at the end of the last block
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Ramps one channel of the measured block from the last gain to the new one
+    pub fn apply(&self, wet: &mut [f32]) {
+        let len = wet.len();
+        for (i, sample) in wet.iter_mut().enumerate() {
+            *sample *= self.last_gain + (self.gain - self.last_gain) * (i + 1) as f32 / len as f32;
+        }
+    }
+}
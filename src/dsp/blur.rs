@@ -0,0 +1,54 @@
+/// How far the blurred magnitudes decay before the tail counts as over
+const TAIL_DECAY_DB: f32 = 96_f32;
+
+/// Length of the tail left by blurring with a time constant of `time` seconds
+pub fn tail_seconds(time: f32) -> f32 {
+    // The magnitudes decay by 20 * log10(e) dB per time constant
+    time * TAIL_DECAY_DB / (20_f32 * std::f32::consts::LOG10_E)
+}
+
+/// Smears the spectrum over time. The magnitude of every bin goes through a one-pole lowpass
+/// from frame to frame, while the sign is taken from the current frame. Smoothing the signed
+/// coefficients would mostly cancel them out, as their signs change with the phase of the input.
+/// Bins that are exactly zero, like in silence after the input stops, keep the sign they had
+/// last, so the tail does not turn into the same all positive frame over and over
+pub struct Blur {
+    magnitudes: Vec<f32>,
+    signs: Vec<f32>,
+}
+
+impl Blur {
+    pub fn new(max_bins: usize) -> Self {
+        Self {
+            magnitudes: vec![0_f32; max_bins],
+            signs: vec![1_f32; max_bins],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.magnitudes.fill(0_f32);
+        self.signs.fill(1_f32);
+    }
+
+    /// `time` is the time constant of the smoothing in seconds. With zero time the state still
+    /// follows the input, so turning the blur up does not bring back old frames
+    pub fn process(&mut self, coefficients: &mut [f32], time: f32, frame_seconds: f32) {
+        let feedback = if time > 0_f32 {
+            (-frame_seconds / time).exp()
+        } else {
+            0_f32
+        };
+
+        for ((coefficient, magnitude), sign) in coefficients
+            .iter_mut()
+            .zip(self.magnitudes.iter_mut())
+            .zip(self.signs.iter_mut())
+        {
+            if *coefficient != 0_f32 {
+                *sign = coefficient.signum();
+            }
+            *magnitude = coefficient.abs() + (*magnitude - coefficient.abs()) * feedback;
+            *coefficient = *magnitude * *sign;
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use nih_plug::util::db_to_gain;
+
+/// Bits spent on the scale factor of every band that gets any bits at all
+const SIDE_INFO_BITS: f32 = 6_f32;
+/// How far below the band energy the masking threshold sits
+const SIGNAL_TO_MASK_DB: f32 = 10_f32;
+const SPREAD_TO_LOWER: f32 = 0.1_f32;
+const SPREAD_TO_HIGHER: f32 = 0.3_f32;
+/// Level of a full scale coefficient, used to place the absolute threshold of hearing
+const FULL_SCALE_SPL_DB: f32 = 96_f32;
+const MIN_BAND_WIDTH_HZ: f32 = 100_f32;
+const BAND_WIDTH_RATIO: f32 = 0.2_f32;
+const ALLOCATION_ITERATIONS: usize = 24;
+const MIN_NOISE_TO_MASK_DB: f32 = -40_f32;
+const MAX_NOISE_TO_MASK_DB: f32 = 80_f32;
+/// Gain compensation in dB at bitrates spread evenly in log2 between the lowest and the highest
+/// one. Measured on pink noise at -18 dBFS RMS through 64 sample blocks, like the crush modes.
+/// Low bitrates lose whole bands, middle ones add more quantization noise than they remove
+const GAIN_DB: [f32; 16] = [
+    16.53_f32, 12.17_f32, 12.21_f32, 10.51_f32, 9.37_f32, 7.81_f32, 6.51_f32, 5.16_f32, 2.75_f32,
+    -3.09_f32, -2.64_f32, 0.67_f32, 0_f32, 0.13_f32, 0.02_f32, 0.05_f32,
+];
+
+/// End of the scale-factor band starting at bin `start`. Bands get wider with frequency,
+/// roughly following the critical bands, and never go past `bins`
+pub fn band_end(start: usize, bin_width: f32, bins: usize) -> usize {
+    let frequency = (start as f32 + 0.5_f32) * bin_width;
+    let width = (MIN_BAND_WIDTH_HZ.max(frequency * BAND_WIDTH_RATIO) / bin_width).ceil();
+    (start + width as usize).clamp(start + 1, bins)
+}
+
+/// Absolute threshold of hearing in dB SPL, after Terhardt
+fn threshold_in_quiet_db(frequency: f32) -> f32 {
+    let f = frequency.max(20_f32) / 1_000_f32;
+    3.64_f32 * f.powf(-0.8_f32) - 6.5_f32 * (-0.6_f32 * (f - 3.3_f32).powi(2)).exp()
+        + 0.001_f32 * f.powi(4)
+}
+
+/// Emulates a perceptual transform codec. Bins are grouped into scale-factor bands roughly
+/// following the critical bands, a masking threshold is estimated for every band and the bit
+/// budget of a block is spent where the quantization noise would be heard the most. Bands
+/// that get no bits are zeroed
+pub struct Codec {
+    band_start: Vec<usize>,
+    band_energy: Vec<f32>,
+    band_peak: Vec<f32>,
+    band_mask: Vec<f32>,
+}
+
+impl Codec {
+    pub fn new(max_bins: usize) -> Self {
+        Self {
+            band_start: vec![0; max_bins + 1],
+            band_energy: vec![0_f32; max_bins],
+            band_peak: vec![0_f32; max_bins],
+            band_mask: vec![0_f32; max_bins],
+        }
+    }
+
+    /// Loudness correction for the level lost at `bitrate` kbps, interpolated between the
+    /// measured points
+    pub fn gain_compensation(bitrate: f32) -> f32 {
+        let (min, max) = (
+            super::MIN_BITRATE_KBPS.log2(),
+            super::MAX_BITRATE_KBPS.log2(),
+        );
+        let position =
+            ((bitrate.log2() - min) / (max - min)).clamp(0_f32, 1_f32) * (GAIN_DB.len() - 1) as f32;
+        let index = (position as usize).min(GAIN_DB.len() - 2);
+        let fraction = position - index as f32;
+        db_to_gain(GAIN_DB[index] + (GAIN_DB[index + 1] - GAIN_DB[index]) * fraction)
+    }
+
+    /// Quantizes `coefficients`, which are the bins from `first_bin` on, `bin_width` Hz apart
+    pub fn process(
+        &mut self,
+        coefficients: &mut [f32],
+        first_bin: usize,
+        bin_width: f32,
+        bit_budget: f32,
+    ) {
+        let bins = coefficients.len();
+
+        // Split the spectrum into bands, measure them and put the threshold in quiet under them.
+        // The bands are laid out on the whole spectrum, so they do not move with the first bin
+        let mut bands = 0;
+        let mut start = 0;
+        while start < bins {
+            let frequency = ((first_bin + start) as f32 + 0.5_f32) * bin_width;
+            let end = band_end(first_bin + start, bin_width, first_bin + bins) - first_bin;
+
+            let band = &coefficients[start..end];
+            self.band_start[bands] = start;
+            self.band_energy[bands] = band.iter().map(|v| v * v).sum();
+            self.band_peak[bands] = band.iter().fold(0_f32, |peak, v| peak.max(v.abs()));
+            self.band_mask[bands] =
+                10_f32.powf((threshold_in_quiet_db(frequency) - FULL_SCALE_SPL_DB) * 0.1_f32);
+
+            bands += 1;
+            start = end;
+        }
+        self.band_start[bands] = bins;
+
+        // Masking threshold per bin, spread to the neighbouring bands
+        let signal_to_mask = 10_f32.powf(-SIGNAL_TO_MASK_DB * 0.1_f32);
+        for band in 0..bands {
+            let mut energy = self.band_energy[band];
+            if band > 0 {
+                energy += SPREAD_TO_HIGHER * self.band_energy[band - 1];
+            }
+            if band + 1 < bands {
+                energy += SPREAD_TO_LOWER * self.band_energy[band + 1];
+            }
+            let width = (self.band_start[band + 1] - self.band_start[band]) as f32;
+            self.band_mask[band] = self.band_mask[band].max(energy * signal_to_mask / width);
+        }
+
+        // Find the lowest noise to mask ratio that fits into the budget
+        let (mut low, mut high) = (MIN_NOISE_TO_MASK_DB, MAX_NOISE_TO_MASK_DB);
+        for _ in 0..ALLOCATION_ITERATIONS {
+            let middle = (low + high) * 0.5_f32;
+            if self.bits_needed(bands, middle) > bit_budget {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        for band in 0..bands {
+            let range = self.band_start[band]..self.band_start[band + 1];
+            match self.step(band, high) {
+                Some(step) => {
+                    for coefficient in coefficients[range].iter_mut() {
+                        *coefficient = (*coefficient / step).round() * step;
+                    }
+                }
+                None => coefficients[range].fill(0_f32),
+            }
+        }
+    }
+
+    /// Quantization step of a band, or `None` if the whole band would round to zero
+    fn step(&self, band: usize, noise_to_mask_db: f32) -> Option<f32> {
+        // Uniform quantization noise has a power of step^2 / 12
+        let noise = self.band_mask[band] * 10_f32.powf(noise_to_mask_db * 0.1_f32);
+        let step = (12_f32 * noise).sqrt();
+        if self.band_peak[band] < step * 0.5_f32 {
+            None
+        } else {
+            Some(step)
+        }
+    }
+
+    fn bits_needed(&self, bands: usize, noise_to_mask_db: f32) -> f32 {
+        (0..bands)
+            .filter_map(|band| {
+                self.step(band, noise_to_mask_db).map(|step| {
+                    let width = (self.band_start[band + 1] - self.band_start[band]) as f32;
+                    width * (2_f32 * self.band_peak[band] / step + 1_f32).log2() + SIDE_INFO_BITS
+                })
+            })
+            .sum()
+    }
+}
@@ -0,0 +1,41 @@
+use super::codec::band_end;
+
+use nih_plug::prelude::Enum;
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum CrossSynthesis {
+    #[id = "off"]
+    #[name = "Off"]
+    Off,
+    /// The sidechain spectrum sets the crunch threshold of every bin
+    #[id = "crunch"]
+    #[name = "Crunch"]
+    Crunch,
+    /// The sidechain spectrum sets the crush step size of every bin
+    #[id = "crush"]
+    #[name = "Crush"]
+    Crush,
+}
+
+/// Turns the sidechain coefficients into a scale for every bin, between `1 - amount` where the
+/// sidechain is silent and 1 in its loudest scale-factor band. The level of a band is its RMS,
+/// so the scale follows the spectral envelope of the sidechain rather than its single bins
+pub fn sidechain_scale(sidechain: &[f32], scale: &mut [f32], amount: f32, bin_width: f32) {
+    let bins = sidechain.len();
+
+    let mut peak = 0_f32;
+    let mut start = 0;
+    while start < bins {
+        let end = band_end(start, bin_width, bins);
+        let band = &sidechain[start..end];
+        let rms = (band.iter().map(|v| v * v).sum::<f32>() / band.len() as f32).sqrt();
+        scale[start..end].fill(rms);
+        peak = peak.max(rms);
+        start = end;
+    }
+
+    for value in scale[..bins].iter_mut() {
+        let envelope = if peak > 0_f32 { *value / peak } else { 0_f32 };
+        *value = amount.mul_add(envelope - 1_f32, 1_f32);
+    }
+}
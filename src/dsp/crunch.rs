@@ -0,0 +1,82 @@
+use nih_plug::prelude::Enum;
+
+use std::f32::consts::FRAC_PI_2;
+
+const MIRROR_BITS: u32 = 16;
+const MIRROR_SCALE: f32 = ((1_u32 << MIRROR_BITS) - 1) as f32;
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum CrunchShape {
+    #[id = "hard"]
+    #[name = "Hard clip"]
+    Hard,
+    #[id = "tanh"]
+    #[name = "Tanh"]
+    Tanh,
+    #[id = "cubic"]
+    #[name = "Cubic"]
+    Cubic,
+    #[id = "sine_fold"]
+    #[name = "Sine fold"]
+    SineFold,
+    #[id = "triangle_fold"]
+    #[name = "Triangle fold"]
+    TriangleFold,
+    #[id = "mirror_fold"]
+    #[name = "Mirror fold"]
+    MirrorFold,
+}
+
+impl CrunchShape {
+    /// Shapes a coefficient so that it stays between `-negative` and `positive`. Both sides are
+    /// normalized to their own threshold, so the shapes are only stretched by asymmetry
+    #[inline]
+    pub fn apply(self, coefficient: f32, positive: f32, negative: f32) -> f32 {
+        let threshold = if coefficient >= 0_f32 {
+            positive
+        } else {
+            negative
+        };
+        if threshold <= 0_f32 {
+            return 0_f32;
+        }
+        let x = coefficient / threshold;
+
+        let y = match self {
+            CrunchShape::Hard => x.clamp(-1_f32, 1_f32),
+            CrunchShape::Tanh => x.tanh(),
+            // Soft knee reaching full scale with zero slope at 1.5
+            CrunchShape::Cubic => {
+                let x = x.clamp(-1.5_f32, 1.5_f32);
+                x - x * x * x * (4_f32 / 27_f32)
+            }
+            CrunchShape::SineFold => (x * FRAC_PI_2).sin(),
+            CrunchShape::TriangleFold => {
+                let phase = (x + 1_f32).rem_euclid(4_f32);
+                if phase < 2_f32 {
+                    phase - 1_f32
+                } else {
+                    3_f32 - phase
+                }
+            }
+            // The part above the threshold is written as a fixed point number, which is read
+            // back with its bits in reverse order
+            CrunchShape::MirrorFold => {
+                let magnitude = x.abs();
+                if magnitude <= 1_f32 {
+                    x
+                } else {
+                    let overflow = ((magnitude - 1_f32).fract() * MIRROR_SCALE) as u32;
+                    let mirrored = overflow.reverse_bits() >> (u32::BITS - MIRROR_BITS);
+                    (1_f32 - mirrored as f32 / MIRROR_SCALE).copysign(x)
+                }
+            }
+        };
+
+        if y >= 0_f32 {
+            y * positive
+        } else {
+            y * negative
+        }
+    }
+}
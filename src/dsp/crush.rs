@@ -0,0 +1,199 @@
+use super::codec::Codec;
+use super::rng::Rng;
+
+use nih_plug::prelude::Enum;
+use nih_plug::util::db_to_gain;
+
+use plugin_utils::dsp_utils::numerical_functions::quartic;
+
+const MU: f32 = 255_f32;
+const MANTISSA_BITS: u32 = 23;
+
+// Gain compensation in dB as quartics of the log2 of the inverse quantization step, highest power
+// first. Measured on pink noise at -18 dBFS RMS through 64 sample blocks, like the crunch one.
+// Floor and ceil measure the same, their errors only differ in sign
+const UNIFORM_GAIN_QUARTIC: [f32; 5] = [
+    0.13073_f32,
+    -2.33165_f32,
+    14.86955_f32,
+    -40.31206_f32,
+    40.09345_f32,
+];
+const MU_LAW_GAIN_QUARTIC: [f32; 5] = [
+    -0.01743_f32,
+    0.37359_f32,
+    -2.95549_f32,
+    10.24473_f32,
+    -13.19809_f32,
+];
+const FLOOR_GAIN_QUARTIC: [f32; 5] = [
+    -0.00844_f32,
+    0.02416_f32,
+    0.13781_f32,
+    5.45909_f32,
+    -33.58267_f32,
+];
+const STOCHASTIC_GAIN_QUARTIC: [f32; 5] = [
+    0.00025_f32,
+    -0.03277_f32,
+    0.10814_f32,
+    2.99586_f32,
+    -15.89947_f32,
+];
+/// Measured gain compensation of mantissa truncation with no bits kept, it halves with every
+/// kept bit
+const MANTISSA_GAIN_DB: f32 = 2.8_f32;
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum CrushMode {
+    #[id = "uniform"]
+    #[name = "Uniform"]
+    Uniform,
+    #[id = "mu_law"]
+    #[name = "Mu-law"]
+    MuLaw,
+    #[id = "floor"]
+    #[name = "Floor"]
+    Floor,
+    #[id = "ceil"]
+    #[name = "Ceil"]
+    Ceil,
+    #[id = "stochastic"]
+    #[name = "Stochastic"]
+    Stochastic,
+    #[id = "mantissa"]
+    #[name = "Mantissa"]
+    Mantissa,
+    /// Set by the bitrate instead of the crush amount, and runs even with crush at zero. It
+    /// ignores crush normalization and crush cross-synthesis
+    #[id = "codec"]
+    #[name = "Codec"]
+    Codec,
+}
+
+/// Perceptual noise substitution. Bins of `crushed` that were quantized to zero are filled with
+/// noise carrying `amount` times the energy they had in `original`. The noise is divided by
+/// `gain`, so it comes out at the right level after gain compensation
+pub fn noise_fill(original: &[f32], crushed: &mut [f32], amount: f32, gain: f32, rng: &mut Rng) {
+    let mut lost_energy = 0_f32;
+    let mut zeroed = 0;
+    for (original, crushed) in original.iter().zip(crushed.iter()) {
+        if *crushed == 0_f32 {
+            lost_energy += original * original;
+            zeroed += 1;
+        }
+    }
+    if lost_energy == 0_f32 {
+        return;
+    }
+
+    // Uniform noise between -1.0 and 1.0 has a power of 1/3
+    let scale = (amount * lost_energy * 3_f32 / zeroed as f32).sqrt() / gain;
+    for coefficient in crushed.iter_mut().filter(|v| **v == 0_f32) {
+        *coefficient = rng.next_f32().mul_add(2_f32, -1_f32) * scale;
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum CrushNormalization {
+    #[id = "off"]
+    #[name = "Off"]
+    Off,
+    #[id = "peak"]
+    #[name = "Peak"]
+    Peak,
+    #[id = "rms"]
+    #[name = "RMS"]
+    Rms,
+}
+
+impl CrushNormalization {
+    /// Level a scale-factor band is divided by before quantizing, or `None` if the band is
+    /// left as it is
+    pub fn scale(self, coefficients: &[f32]) -> Option<f32> {
+        let scale = match self {
+            CrushNormalization::Off => return None,
+            CrushNormalization::Peak => {
+                coefficients.iter().fold(0_f32, |peak, v| peak.max(v.abs()))
+            }
+            CrushNormalization::Rms => {
+                (coefficients.iter().map(|v| v * v).sum::<f32>() / coefficients.len() as f32).sqrt()
+            }
+        };
+        if scale > 0_f32 {
+            Some(scale)
+        } else {
+            None
+        }
+    }
+}
+
+impl CrushMode {
+    /// Quantizes the coefficients with a step of `1 / multiplier`. Mantissa mode instead keeps
+    /// as many mantissa bits as the step would give below 1.0
+    pub fn apply(self, coefficients: &mut [f32], multiplier: f32, rng: &mut Rng) {
+        match self {
+            CrushMode::Uniform => {
+                for coefficient in coefficients.iter_mut() {
+                    *coefficient = (*coefficient * multiplier).round() / multiplier;
+                }
+            }
+            CrushMode::MuLaw => {
+                let log_mu = MU.ln_1p();
+                for coefficient in coefficients.iter_mut() {
+                    let compressed = (MU * coefficient.abs()).ln_1p() / log_mu;
+                    let quantized = (compressed * multiplier).round() / multiplier;
+                    *coefficient = ((quantized * log_mu).exp_m1() / MU).copysign(*coefficient);
+                }
+            }
+            CrushMode::Floor => {
+                for coefficient in coefficients.iter_mut() {
+                    *coefficient = (*coefficient * multiplier).floor() / multiplier;
+                }
+            }
+            CrushMode::Ceil => {
+                for coefficient in coefficients.iter_mut() {
+                    *coefficient = (*coefficient * multiplier).ceil() / multiplier;
+                }
+            }
+            CrushMode::Stochastic => {
+                for coefficient in coefficients.iter_mut() {
+                    *coefficient =
+                        (*coefficient * multiplier + rng.next_f32()).floor() / multiplier;
+                }
+            }
+            CrushMode::Mantissa => {
+                let mask = !((1_u32 << (MANTISSA_BITS - Self::mantissa_bits(multiplier))) - 1);
+                for coefficient in coefficients.iter_mut() {
+                    *coefficient = f32::from_bits(coefficient.to_bits() & mask);
+                }
+            }
+            // Needs state across the whole spectrum, so it is run by `Codec` instead
+            CrushMode::Codec => (),
+        }
+    }
+
+    /// Loudness correction for quantizing with a step of `1 / multiplier`. Rounding silences
+    /// the quiet coefficients, while flooring, ceiling and dithering add more noise than they
+    /// remove, so some modes boost and others cut. The codec ignores the step and depends on
+    /// the `bitrate` instead
+    pub fn gain_compensation(self, multiplier: f32, bitrate: f32) -> f32 {
+        let quartic_db =
+            |[a, b, c, d, e]: [f32; 5]| db_to_gain(quartic(multiplier.log2(), a, b, c, d, e));
+        match self {
+            CrushMode::Uniform => quartic_db(UNIFORM_GAIN_QUARTIC),
+            CrushMode::MuLaw => quartic_db(MU_LAW_GAIN_QUARTIC),
+            CrushMode::Floor | CrushMode::Ceil => quartic_db(FLOOR_GAIN_QUARTIC),
+            CrushMode::Stochastic => quartic_db(STOCHASTIC_GAIN_QUARTIC),
+            CrushMode::Mantissa => {
+                db_to_gain(MANTISSA_GAIN_DB * 0.5_f32.powi(Self::mantissa_bits(multiplier) as i32))
+            }
+            CrushMode::Codec => Codec::gain_compensation(bitrate),
+        }
+    }
+
+    /// Mantissa bits kept for a step of `1 / multiplier`
+    pub fn mantissa_bits(multiplier: f32) -> u32 {
+        (multiplier.log2().floor().max(1_f32) as u32 - 1).min(MANTISSA_BITS)
+    }
+}
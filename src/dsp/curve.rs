@@ -0,0 +1,60 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+pub const CURVE_POINTS: usize = 32;
+pub const CURVE_MIN_HZ: f32 = 20_f32;
+pub const CURVE_MAX_HZ: f32 = 20_000_f32;
+pub const CURVE_RANGE_DB: f32 = 24_f32;
+pub const CURVE_MAX_TILT_DB: f32 = 6_f32;
+const CURVE_TILT_CENTER_HZ: f32 = 1_000_f32;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum CurveMode {
+    Tilt,
+    FreeHand,
+}
+
+/// Per-bin scaling of the crunch threshold. The free-hand curve is made of breakpoints evenly
+/// spaced on a log-frequency axis between `CURVE_MIN_HZ` and `CURVE_MAX_HZ`, in dB
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct CrunchCurve {
+    pub mode: CurveMode,
+    /// Slope in dB per octave, centered around 1 kHz
+    pub tilt: f32,
+    pub points: [f32; CURVE_POINTS],
+}
+
+impl Default for CrunchCurve {
+    fn default() -> Self {
+        Self {
+            mode: CurveMode::Tilt,
+            tilt: 0_f32,
+            points: [0_f32; CURVE_POINTS],
+        }
+    }
+}
+
+impl CrunchCurve {
+    /// Position of a frequency on the curve axis, from 0 at `CURVE_MIN_HZ` to 1 at
+    /// `CURVE_MAX_HZ`
+    pub fn frequency_to_position(frequency: f32) -> f32 {
+        ((frequency / CURVE_MIN_HZ).ln() / (CURVE_MAX_HZ / CURVE_MIN_HZ).ln()).clamp(0_f32, 1_f32)
+    }
+
+    pub fn position_to_frequency(position: f32) -> f32 {
+        CURVE_MIN_HZ * (CURVE_MAX_HZ / CURVE_MIN_HZ).powf(position)
+    }
+
+    pub fn gain_db_at(&self, frequency: f32) -> f32 {
+        match self.mode {
+            CurveMode::Tilt => (self.tilt * (frequency.max(1_f32) / CURVE_TILT_CENTER_HZ).log2())
+                .clamp(-CURVE_RANGE_DB, CURVE_RANGE_DB),
+            CurveMode::FreeHand => {
+                let position = Self::frequency_to_position(frequency) * (CURVE_POINTS - 1) as f32;
+                let index = (position as usize).min(CURVE_POINTS - 2);
+                let fraction = position - index as f32;
+                self.points[index] + (self.points[index + 1] - self.points[index]) * fraction
+            }
+        }
+    }
+}
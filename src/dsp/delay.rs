@@ -0,0 +1,42 @@
+/// Keeps the most recent samples of a signal, so they can be read back with any delay up to the
+/// capacity without allocating
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl DelayLine {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: vec![0_f32; capacity],
+            position: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0_f32);
+        self.position = 0;
+    }
+
+    pub fn write(&mut self, samples: &[f32]) {
+        for sample in samples {
+            self.buffer[self.position] = *sample;
+            self.position = (self.position + 1) % self.buffer.len();
+        }
+    }
+
+    /// The sample written `delay` samples before the next one
+    pub fn get(&self, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        debug_assert!(delay > 0 && delay <= len);
+        self.buffer[(self.position + len - delay) % len]
+    }
+
+    /// Fills `output` with the samples starting `delay` samples before the next write
+    pub fn read(&self, delay: usize, output: &mut [f32]) {
+        debug_assert!(output.len() <= delay);
+        for (i, sample) in output.iter_mut().enumerate() {
+            *sample = self.get(delay - i);
+        }
+    }
+}
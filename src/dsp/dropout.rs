@@ -0,0 +1,121 @@
+use super::codec::band_end;
+use super::rng::Rng;
+
+use nih_plug::prelude::Enum;
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum DropoutMode {
+    #[id = "bin"]
+    #[name = "Bin"]
+    Bin,
+    #[id = "band"]
+    #[name = "Band"]
+    Band,
+    #[id = "frame"]
+    #[name = "Frame"]
+    Frame,
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum DropoutConcealment {
+    #[id = "mute"]
+    #[name = "Mute"]
+    Mute,
+    #[id = "repeat"]
+    #[name = "Repeat"]
+    Repeat,
+}
+
+/// Mixed into the seed, so the losses do not follow the stutter decisions
+const SEED_OFFSET: u64 = 0x0D20_9047;
+
+/// Decides which coefficients are lost. It is shared by all channels, so they lose the same
+/// bins, bands and frames. Every bin, scale-factor band or frame is lost with the same
+/// probability
+pub struct DropoutPattern {
+    seed: u64,
+    rng: Rng,
+}
+
+impl DropoutPattern {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: Rng::new(seed ^ SEED_OFFSET),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.seed);
+    }
+
+    /// Restarts the random sequence if the seed changed
+    pub fn set_seed(&mut self, seed: u64) {
+        if seed != self.seed {
+            *self = Self::new(seed);
+        }
+    }
+
+    /// Rolls the losses of the next frame into `lost`, one flag per bin
+    pub fn next_frame(&mut self, lost: &mut [bool], mode: DropoutMode, rate: f32, bin_width: f32) {
+        let bins = lost.len();
+        if rate <= 0_f32 {
+            lost.fill(false);
+            return;
+        }
+
+        let mut start = 0;
+        while start < bins {
+            let end = match mode {
+                DropoutMode::Bin => start + 1,
+                DropoutMode::Band => band_end(start, bin_width, bins),
+                DropoutMode::Frame => bins,
+            };
+            lost[start..end].fill(self.rng.next_f32() < rate);
+            start = end;
+        }
+    }
+}
+
+/// Emulates packet loss. Lost coefficients are either muted or replaced with what was played in
+/// the last frame
+pub struct Dropout {
+    previous: Vec<f32>,
+}
+
+impl Dropout {
+    pub fn new(max_bins: usize) -> Self {
+        Self {
+            previous: vec![0_f32; max_bins],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.previous.fill(0_f32);
+    }
+
+    /// Conceals the coefficients flagged in `lost`
+    pub fn process(
+        &mut self,
+        coefficients: &mut [f32],
+        lost: &[bool],
+        concealment: DropoutConcealment,
+    ) {
+        let bins = coefficients.len();
+        let previous = &mut self.previous[..bins];
+
+        for ((coefficient, previous), lost) in
+            coefficients.iter_mut().zip(previous.iter()).zip(lost)
+        {
+            if *lost {
+                *coefficient = match concealment {
+                    DropoutConcealment::Mute => 0_f32,
+                    DropoutConcealment::Repeat => *previous,
+                };
+            }
+        }
+
+        // Repeated bins are remembered as well, so a run of losses keeps holding the same frame
+        previous.copy_from_slice(coefficients);
+    }
+}
@@ -0,0 +1,490 @@
+use super::auto_gain::AutoGain;
+use super::block_size_from_log2;
+use super::blur::tail_seconds;
+use super::delay::DelayLine;
+use super::dropout::DropoutPattern;
+use super::latency_samples;
+use super::mdct::window_alpha;
+use super::stutter::Stutter;
+use super::stutter::StutterFrame;
+use super::CrunchyParamsBlock;
+use super::CrunchySingleChannelProcessor;
+use super::EnvelopeSource;
+use super::WindowShape;
+use super::MAX_BLOCK_SIZE;
+use crate::CrunchyParams;
+use std::sync::Arc;
+
+use nih_plug::prelude::Buffer;
+use nih_plug::prelude::ProcessStatus;
+use nih_plug::prelude::Transport;
+
+use plugin_utils::dsp_utils::ParamsBlock;
+use plugin_utils::dsp_utils::SingleChannelProcessor;
+
+/// Blocks a new block size is warmed up with. The first one fills the input of the transform,
+/// the second one its overlap, and the output of the third one is the first to be played
+const WARM_UP_BLOCKS: usize = 3;
+
+struct ChannelState {
+    processor: CrunchySingleChannelProcessor,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    sidechain: Vec<f32>,
+    // Output of the transform of a new block size while it is warmed up
+    scratch: Vec<f32>,
+    // Input of the last blocks, so a new block size can be warmed up with them
+    history: DelayLine,
+    // Sidechain of the last blocks, so listening to it has the same latency as the processing
+    sidechain_history: DelayLine,
+}
+
+/// Collects the host buffers into transform blocks and runs them through one
+/// `CrunchySingleChannelProcessor` per channel. Everything is allocated for the largest block
+/// size up front, so the block size can be switched while processing
+pub struct CrunchyEngine {
+    params: Arc<CrunchyParams>,
+    params_block: CrunchyParamsBlock,
+    channels: Vec<ChannelState>,
+
+    block_size: usize,
+    position: usize,
+    // Window of the transforms, and the window parameters read with the last block
+    window: (WindowShape, f32),
+    next_window: (WindowShape, f32),
+
+    // Freeze is also held by MIDI notes. Notes shorter than a block still freeze one block
+    held_notes: usize,
+    note_triggered: bool,
+
+    stutter: Stutter,
+    dropout: DropoutPattern,
+    auto_gain: AutoGain,
+}
+
+impl CrunchyEngine {
+    pub fn new(params: Arc<CrunchyParams>, channels: usize, sample_rate: f32) -> Self {
+        let block_size = block_size_from_log2(params.block_size.value());
+        let mut params_block = CrunchyParamsBlock::new(params.clone(), block_size);
+        params_block.sample_rate = sample_rate;
+        params_block.channels = channels;
+        let seed = params.seed.value() as u64;
+        let window = read_window(&params);
+
+        Self {
+            params,
+            params_block,
+            channels: (0..channels)
+                .map(|channel| ChannelState {
+                    processor: {
+                        let mut processor = CrunchySingleChannelProcessor::new(block_size);
+                        processor.set_channel(channel);
+                        processor.set_transform(block_size, window.0, window.1);
+                        processor
+                    },
+                    input: vec![0_f32; MAX_BLOCK_SIZE],
+                    output: vec![0_f32; MAX_BLOCK_SIZE],
+                    sidechain: vec![0_f32; MAX_BLOCK_SIZE],
+                    scratch: vec![0_f32; MAX_BLOCK_SIZE],
+                    history: DelayLine::new(MAX_BLOCK_SIZE * WARM_UP_BLOCKS),
+                    sidechain_history: DelayLine::new(MAX_BLOCK_SIZE * WARM_UP_BLOCKS),
+                })
+                .collect(),
+            block_size,
+            position: 0,
+            window,
+            next_window: window,
+            held_notes: 0,
+            note_triggered: false,
+            stutter: Stutter::new(seed),
+            dropout: DropoutPattern::new(seed),
+            auto_gain: AutoGain::default(),
+        }
+    }
+
+    pub fn latency_samples(&self) -> u32 {
+        latency_samples(self.block_size)
+    }
+
+    /// Clears all buffered audio, so a restarted transport renders exactly like the first time
+    pub fn reset(&mut self) {
+        self.params_block.reset();
+        for channel in self.channels.iter_mut() {
+            channel.input.fill(0_f32);
+            channel.output.fill(0_f32);
+            channel.sidechain.fill(0_f32);
+            channel.scratch.fill(0_f32);
+            channel.history.reset();
+            channel.sidechain_history.reset();
+            channel.processor.reset();
+        }
+        self.position = 0;
+        self.held_notes = 0;
+        self.note_triggered = false;
+        self.stutter.reset();
+        self.dropout.reset();
+        self.auto_gain.reset();
+    }
+
+    pub fn note_on(&mut self) {
+        self.held_notes += 1;
+        self.note_triggered = true;
+    }
+
+    pub fn note_off(&mut self) {
+        self.held_notes = self.held_notes.saturating_sub(1);
+    }
+
+    /// Whether the frozen frame is still being played back, in which case the output does not
+    /// depend on the input and the host has to keep processing
+    fn is_frozen(&self) -> bool {
+        self.params_block.freeze || self.held_notes > 0
+    }
+
+    /// Processes `buffer` in place. Missing sidechain channels are treated as silence
+    pub fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        sidechain: &[&mut [f32]],
+        transport: &Transport,
+    ) -> ProcessStatus {
+        let beats = transport.pos_beats().filter(|_| transport.playing);
+        self.process_channels(buffer.as_slice(), sidechain, beats, transport.tempo)
+    }
+
+    /// Processes the channels in place. `beats` is the position of the first sample in quarter
+    /// notes while the transport is playing
+    fn process_channels(
+        &mut self,
+        channel_buffers: &mut [&mut [f32]],
+        sidechain: &[&mut [f32]],
+        beats: Option<f64>,
+        tempo: Option<f64>,
+    ) -> ProcessStatus {
+        let num_samples = channel_buffers.first().map_or(0, |v| v.len());
+
+        let mut start = 0;
+        while start < num_samples {
+            let len = (self.block_size - self.position).min(num_samples - start);
+
+            // The output of the previous block is played back while the next one is collected
+            for (i, (channel, samples)) in self
+                .channels
+                .iter_mut()
+                .zip(channel_buffers.iter_mut())
+                .enumerate()
+            {
+                let samples = &mut samples[start..start + len];
+                let range = self.position..self.position + len;
+                channel.input[range.clone()].copy_from_slice(samples);
+                match sidechain.get(i) {
+                    Some(v) => {
+                        channel.sidechain[range.clone()].copy_from_slice(&v[start..start + len])
+                    }
+                    None => channel.sidechain[range.clone()].fill(0_f32),
+                }
+                samples.copy_from_slice(&channel.output[range]);
+            }
+
+            start += len;
+            self.position += len;
+            if self.position == self.block_size {
+                self.position = 0;
+
+                // Position of the first sample of the collected block, in quarter notes
+                let position = match (beats, tempo) {
+                    (Some(beats), Some(tempo)) => Some(
+                        beats
+                            + (start as f64 - self.block_size as f64)
+                                / self.params_block.sample_rate as f64
+                                * tempo
+                                / 60_f64,
+                    ),
+                    _ => None,
+                };
+                let seed = self.params.seed.value() as u64;
+                self.stutter.set_seed(seed);
+                self.params_block.stutter_frame = self.stutter.next_frame(
+                    position,
+                    tempo.unwrap_or(120_f64),
+                    self.params.stutter_division.value(),
+                    self.params.stutter_probability.value(),
+                    self.params_block.sample_rate,
+                    self.block_size,
+                );
+                self.dropout.set_seed(seed);
+                self.dropout.next_frame(
+                    &mut self.params_block.dropout_lost[..self.block_size],
+                    self.params.dropout_mode.value(),
+                    self.params.dropout_rate.value(),
+                    self.params_block.sample_rate / (self.block_size * 2) as f32,
+                );
+
+                if let ProcessStatus::Error(e) = self.process_block() {
+                    return ProcessStatus::Error(e);
+                }
+            }
+        }
+
+        // Blurred magnitudes keep ringing after the input stops, and come out delayed by the
+        // latency
+        let blur = self.params.blur.value() * 0.001_f32;
+        if self.is_frozen() {
+            ProcessStatus::KeepAlive
+        } else if blur > 0_f32 {
+            ProcessStatus::Tail(
+                (tail_seconds(blur) * self.params_block.sample_rate) as u32
+                    + self.latency_samples(),
+            )
+        } else {
+            ProcessStatus::Normal
+        }
+    }
+
+    fn process_block(&mut self) -> ProcessStatus {
+        // The envelope follows the loudest channel, so all channels are modulated the same way
+        let source = self.params.envelope_source.value();
+        for i in 0..self.block_size {
+            self.params_block.envelope[i] = self.channels.iter().fold(0_f32, |peak, channel| {
+                let sample = match source {
+                    EnvelopeSource::Input => channel.input[i],
+                    EnvelopeSource::Sidechain => channel.sidechain[i],
+                };
+                peak.max(sample.abs())
+            });
+        }
+        self.params_block.from_params();
+        self.params_block.freeze |= self.held_notes > 0 || self.note_triggered;
+        self.note_triggered = false;
+
+        let block_size = self.block_size;
+        for channel in self.channels.iter_mut() {
+            channel
+                .processor
+                .process_sidechain(&channel.sidechain[..block_size], &self.params_block);
+            channel.processor.process_wet(
+                &channel.input[..block_size],
+                &mut channel.output[..block_size],
+                &self.params_block,
+            );
+            channel.history.write(&channel.input[..block_size]);
+            channel
+                .sidechain_history
+                .write(&channel.sidechain[..block_size]);
+        }
+
+        // Level the processed signal with the dry one, which is delayed by as much. All
+        // channels share one gain, so the balance between them does not shift
+        if self.params_block.auto_gain {
+            self.auto_gain.update(
+                self.channels.iter().map(|channel| channel.processor.dry()),
+                self.channels
+                    .iter()
+                    .map(|channel| &channel.output[..block_size]),
+                block_size,
+                self.params_block.auto_gain_time * 0.001_f32,
+                self.params_block.auto_gain_ceiling,
+                self.params_block.sample_rate,
+            );
+            for channel in self.channels.iter_mut() {
+                self.auto_gain.apply(&mut channel.output[..block_size]);
+            }
+        }
+
+        for channel in self.channels.iter_mut() {
+            channel
+                .processor
+                .mix(&mut channel.output[..block_size], &self.params_block);
+        }
+
+        // Listening to the sidechain bypasses the processing, but keeps its latency
+        if self.params.sidechain_listen.value() {
+            for channel in self.channels.iter_mut() {
+                channel
+                    .sidechain_history
+                    .read(block_size * 2, &mut channel.output[..block_size]);
+            }
+        }
+
+        self.update_transform();
+
+        ProcessStatus::Normal
+    }
+
+    /// Block size and window changes are only picked up between blocks. Swapping the window under
+    /// an existing overlap would break the reconstruction, so the transform is set up anew and
+    /// warmed up with the last blocks of the input, which fills its overlap and already processes
+    /// its first output. The block that was just processed is crossfaded into that output, which
+    /// lines up with the new latency. While listening, the sidechain is crossfaded to the new
+    /// latency instead
+    fn update_transform(&mut self) {
+        let block_size = block_size_from_log2(self.params.block_size.value());
+        // Every window change rebuilds the window, so it is only applied once the window
+        // parameters stop moving
+        let window = read_window(&self.params);
+        let window_settled = window == self.next_window;
+        self.next_window = window;
+        let window = if window_settled { window } else { self.window };
+        if block_size == self.block_size && window == self.window {
+            return;
+        }
+
+        let fade_len = block_size.min(self.block_size);
+        self.block_size = block_size;
+        self.window = window;
+        self.params_block.set_block_size(block_size);
+        // Recorded frames only fit the old transform
+        self.stutter.stop();
+        self.params_block.stutter_frame = StutterFrame::Off;
+
+        let listen = self.params.sidechain_listen.value();
+        for channel in self.channels.iter_mut() {
+            // The output of the last block that is run through is the one played next
+            channel
+                .processor
+                .set_transform(block_size, window.0, window.1);
+            for blocks in (1..=WARM_UP_BLOCKS).rev() {
+                let delay = block_size * blocks;
+                channel
+                    .history
+                    .read(delay, &mut channel.input[..block_size]);
+                channel
+                    .sidechain_history
+                    .read(delay, &mut channel.sidechain[..block_size]);
+                channel
+                    .processor
+                    .process_sidechain(&channel.sidechain[..block_size], &self.params_block);
+                channel.processor.process_wet(
+                    &channel.input[..block_size],
+                    &mut channel.scratch[..block_size],
+                    &self.params_block,
+                );
+                if self.params_block.auto_gain {
+                    let gain = self.auto_gain.gain();
+                    channel.scratch[..block_size]
+                        .iter_mut()
+                        .for_each(|v| *v *= gain);
+                }
+                channel
+                    .processor
+                    .mix(&mut channel.scratch[..block_size], &self.params_block);
+            }
+            if listen {
+                channel
+                    .sidechain_history
+                    .read(block_size * 2, &mut channel.scratch[..block_size]);
+            }
+
+            for (i, (sample, new)) in channel.output[..block_size]
+                .iter_mut()
+                .zip(channel.scratch.iter())
+                .enumerate()
+            {
+                let fade = ((i + 1) as f32 / fade_len as f32).min(1_f32);
+                let old = if i < fade_len { *sample } else { 0_f32 };
+                *sample = old.mul_add(1_f32 - fade, new * fade);
+            }
+        }
+    }
+}
+
+/// Window shape and the alpha it is built with
+fn read_window(params: &CrunchyParams) -> (WindowShape, f32) {
+    let shape = params.window.value();
+    (shape, window_alpha(shape, params.window_alpha.value()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CrushMode;
+    use crate::DropoutConcealment;
+    use crate::DropoutMode;
+
+    use nih_plug::prelude::EnumParam;
+    use nih_plug::prelude::FloatParam;
+    use nih_plug::prelude::FloatRange;
+
+    const SAMPLE_RATE: f32 = 44_100_f32;
+    const TEMPO: f64 = 120_f64;
+    // Does not line up with the blocks on purpose
+    const BUFFER_SIZE: usize = 100;
+
+    fn amount(name: &str, value: f32) -> FloatParam {
+        FloatParam::new(
+            name,
+            value,
+            FloatRange::Linear {
+                min: 0_f32,
+                max: 1_f32,
+            },
+        )
+    }
+
+    /// Renders one second of a saw per channel with the transport playing from the start
+    fn render(engine: &mut CrunchyEngine, frequencies: &[f32]) -> Vec<Vec<f32>> {
+        let len = SAMPLE_RATE as usize;
+        let mut channels: Vec<Vec<f32>> = frequencies
+            .iter()
+            .map(|frequency| {
+                (0..len)
+                    .map(|i| (i as f32 * frequency / SAMPLE_RATE).fract() - 0.5_f32)
+                    .collect()
+            })
+            .collect();
+
+        let mut start = 0;
+        while start < len {
+            let end = (start + BUFFER_SIZE).min(len);
+            let mut buffers: Vec<&mut [f32]> =
+                channels.iter_mut().map(|v| &mut v[start..end]).collect();
+            let beats = start as f64 / SAMPLE_RATE as f64 * TEMPO / 60_f64;
+            engine.process_channels(&mut buffers, &[], Some(beats), Some(TEMPO));
+            start = end;
+        }
+        channels
+    }
+
+    /// A bounce after a reset has to be bit-identical to the first one, with every random
+    /// process enabled
+    #[test]
+    fn reset_renders_identically() {
+        let params = Arc::new(CrunchyParams {
+            crush_mode: EnumParam::new("Crush mode", CrushMode::Stochastic),
+            noise_fill: amount("Noise fill", 0.5_f32),
+            dropout_mode: EnumParam::new("Dropout mode", DropoutMode::Band),
+            dropout_concealment: EnumParam::new("Dropout concealment", DropoutConcealment::Repeat),
+            dropout_rate: amount("Dropout rate", 0.2_f32),
+            stutter_probability: amount("Stutter probability", 0.5_f32),
+            ..CrunchyParams::default()
+        });
+        let mut engine = CrunchyEngine::new(params, 2, SAMPLE_RATE);
+
+        engine.reset();
+        let first = render(&mut engine, &[110_f32, 111_f32]);
+        engine.reset();
+        let second = render(&mut engine, &[110_f32, 111_f32]);
+
+        assert!(first.iter().flatten().any(|v| *v != 0_f32));
+        assert_eq!(first, second);
+    }
+
+    /// All channels have to lose the same coefficients, so the stereo image holds up
+    #[test]
+    fn dropout_is_shared() {
+        let render_with = |params: CrunchyParams| {
+            let mut engine = CrunchyEngine::new(Arc::new(params), 2, SAMPLE_RATE);
+            engine.reset();
+            render(&mut engine, &[110_f32, 110_f32])
+        };
+        let dry = render_with(CrunchyParams::default());
+        let wet = render_with(CrunchyParams {
+            dropout_mode: EnumParam::new("Dropout mode", DropoutMode::Bin),
+            dropout_rate: amount("Dropout rate", 0.5_f32),
+            ..CrunchyParams::default()
+        });
+
+        assert!(wet[0] != dry[0]);
+        assert!(wet[0] == wet[1]);
+    }
+}
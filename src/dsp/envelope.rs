@@ -0,0 +1,44 @@
+use nih_plug::prelude::Enum;
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum EnvelopeSource {
+    #[id = "input"]
+    #[name = "Input"]
+    Input,
+    #[id = "sidechain"]
+    #[name = "Sidechain"]
+    Sidechain,
+}
+
+/// Peak envelope follower with separate attack and release times
+pub struct EnvelopeFollower {
+    envelope: f32,
+}
+
+impl Default for EnvelopeFollower {
+    fn default() -> Self {
+        Self { envelope: 0_f32 }
+    }
+}
+
+impl EnvelopeFollower {
+    /// One-pole feedback coefficient for a time constant in milliseconds
+    pub fn coefficient(time_ms: f32, sample_rate: f32) -> f32 {
+        (-1_f32 / (time_ms * 0.001_f32 * sample_rate)).exp()
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0_f32;
+    }
+
+    pub fn next(&mut self, input: f32, attack: f32, release: f32) -> f32 {
+        let input = input.abs();
+        let coefficient = if input > self.envelope {
+            attack
+        } else {
+            release
+        };
+        self.envelope = input + (self.envelope - input) * coefficient;
+        self.envelope
+    }
+}
@@ -0,0 +1,33 @@
+use super::rng::Rng;
+
+/// Moves every coefficient up by `bins`, or down if it is negative. Coefficients shifted past
+/// either end are lost, the bins left behind are silent
+pub fn shift(coefficients: &mut [f32], bins: i32) {
+    let len = coefficients.len();
+    let distance = (bins.unsigned_abs() as usize).min(len);
+    if bins > 0 {
+        coefficients.copy_within(..len - distance, distance);
+        coefficients[..distance].fill(0_f32);
+    } else if bins < 0 {
+        coefficients.copy_within(distance.., 0);
+        coefficients[len - distance..].fill(0_f32);
+    }
+}
+
+/// Shuffles the coefficients with a Fisher-Yates shuffle, where every coefficient is only
+/// swapped with one at most `amount` of the range below it. The permutation only depends on
+/// `seed`, so every frame and every channel is scrambled the same way
+pub fn scramble(coefficients: &mut [f32], amount: f32, seed: u64) {
+    let len = coefficients.len();
+    let span = (amount * len as f32).round() as usize;
+    if span == 0 {
+        return;
+    }
+
+    let mut rng = Rng::new(seed);
+    for i in (1..len).rev() {
+        let low = i.saturating_sub(span);
+        let j = low + (rng.next_u32() as usize) % (i - low + 1);
+        coefficients.swap(i, j);
+    }
+}
@@ -0,0 +1,38 @@
+use super::codec::band_end;
+
+/// Emulates spectral band replication. Everything from bin `crossover` up is discarded and
+/// rebuilt from the octave below it. Every scale-factor band of the copy is then scaled to the
+/// energy the discarded band had, times `gain`, the way the envelope sent by the encoder would
+pub fn replicate(coefficients: &mut [f32], crossover: usize, bin_width: f32, gain: f32) {
+    let bins = coefficients.len();
+    let patch_start = crossover / 2;
+    let patch_width = crossover - patch_start;
+
+    let mut start = crossover;
+    while start < bins {
+        let end = band_end(start, bin_width, bins);
+        let energy: f32 = coefficients[start..end].iter().map(|v| v * v).sum();
+
+        if patch_width == 0 {
+            coefficients[start..end].fill(0_f32);
+        } else {
+            // The patch is repeated for as long as it takes to cover the whole range, and only
+            // reads bins below the crossover, which are never overwritten
+            for bin in start..end {
+                coefficients[bin] = coefficients[patch_start + (bin - crossover) % patch_width];
+            }
+        }
+
+        let replicated: f32 = coefficients[start..end].iter().map(|v| v * v).sum();
+        let scale = if replicated > 0_f32 {
+            gain * (energy / replicated).sqrt()
+        } else {
+            0_f32
+        };
+        for coefficient in coefficients[start..end].iter_mut() {
+            *coefficient *= scale;
+        }
+
+        start = end;
+    }
+}
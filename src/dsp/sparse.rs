@@ -0,0 +1,54 @@
+/// Keeps only the loudest coefficients of a block. The magnitudes are sorted in a preallocated
+/// buffer, so this does not allocate on the audio thread
+pub struct Sparsifier {
+    magnitudes: Vec<f32>,
+}
+
+impl Sparsifier {
+    pub fn new(max_bins: usize) -> Self {
+        Self {
+            magnitudes: vec![0_f32; max_bins],
+        }
+    }
+
+    /// Zeros everything but the `count` largest coefficients, and everything more than
+    /// `threshold` below the peak
+    pub fn process(&mut self, coefficients: &mut [f32], count: usize, threshold: f32) {
+        if count == 0 {
+            coefficients.fill(0_f32);
+            return;
+        }
+
+        let bins = coefficients.len();
+        let magnitudes = &mut self.magnitudes[..bins];
+        for (magnitude, coefficient) in magnitudes.iter_mut().zip(coefficients.iter()) {
+            *magnitude = coefficient.abs();
+        }
+        let peak = magnitudes.iter().fold(0_f32, |peak, v| peak.max(*v));
+
+        // Magnitude of the smallest coefficient that is kept, ties are settled by position
+        let mut floor = peak * threshold;
+        let mut ties = usize::MAX;
+        if count < bins {
+            let (_, nth, _) = magnitudes.select_nth_unstable_by(count - 1, |a, b| b.total_cmp(a));
+            let nth = *nth;
+            if nth >= floor {
+                floor = nth;
+                ties = count - magnitudes[..count - 1].iter().filter(|v| **v > nth).count();
+            }
+        }
+
+        for coefficient in coefficients.iter_mut() {
+            let magnitude = coefficient.abs();
+            if magnitude < floor {
+                *coefficient = 0_f32;
+            } else if magnitude == floor {
+                if ties == 0 {
+                    *coefficient = 0_f32;
+                } else {
+                    ties = ties.saturating_sub(1);
+                }
+            }
+        }
+    }
+}
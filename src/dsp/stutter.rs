@@ -0,0 +1,135 @@
+use super::rng::Rng;
+
+use nih_plug::prelude::Enum;
+
+/// Space for the recorded frames of one channel, in coefficients
+pub const MAX_STUTTER_SAMPLES: usize = 1 << 18;
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum StutterDivision {
+    #[id = "quarter"]
+    #[name = "1/4"]
+    Quarter,
+    #[id = "eighth"]
+    #[name = "1/8"]
+    Eighth,
+    #[id = "eighth_triplet"]
+    #[name = "1/8T"]
+    EighthTriplet,
+    #[id = "sixteenth"]
+    #[name = "1/16"]
+    Sixteenth,
+    #[id = "sixteenth_triplet"]
+    #[name = "1/16T"]
+    SixteenthTriplet,
+    #[id = "thirty_second"]
+    #[name = "1/32"]
+    ThirtySecond,
+}
+
+impl StutterDivision {
+    /// Length of the division in quarter notes
+    pub fn beats(self) -> f64 {
+        match self {
+            StutterDivision::Quarter => 1_f64,
+            StutterDivision::Eighth => 0.5_f64,
+            StutterDivision::EighthTriplet => 1_f64 / 3_f64,
+            StutterDivision::Sixteenth => 0.25_f64,
+            StutterDivision::SixteenthTriplet => 1_f64 / 6_f64,
+            StutterDivision::ThirtySecond => 0.125_f64,
+        }
+    }
+}
+
+/// What a processor does with the current frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StutterFrame {
+    Off,
+    /// Plays the frame and stores it at the given index
+    Record(usize),
+    /// Replaces the frame with the one stored at the given index
+    Play(usize),
+}
+
+/// Decides which beats get stuttered. It is shared by all channels, so they loop in sync. At
+/// the start of every beat a die is rolled, and if the beat is stuttered, the first division of
+/// it is recorded and then looped until the next beat
+pub struct Stutter {
+    seed: u64,
+    rng: Rng,
+    beat: Option<i64>,
+    active: bool,
+    frame: usize,
+}
+
+impl Stutter {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: Rng::new(seed),
+            beat: None,
+            active: false,
+            frame: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.seed);
+    }
+
+    /// Restarts the random sequence if the seed changed
+    pub fn set_seed(&mut self, seed: u64) {
+        if seed != self.seed {
+            self.seed = seed;
+            self.rng = Rng::new(seed);
+        }
+    }
+
+    /// Advances by one block starting at `position` quarter notes, or stops when the transport
+    /// does not provide a position
+    pub fn next_frame(
+        &mut self,
+        position: Option<f64>,
+        tempo: f64,
+        division: StutterDivision,
+        probability: f32,
+        sample_rate: f32,
+        block_size: usize,
+    ) -> StutterFrame {
+        let position = match position {
+            Some(v) if probability > 0_f32 => v,
+            _ => {
+                self.beat = None;
+                self.active = false;
+                return StutterFrame::Off;
+            }
+        };
+
+        let beat = position.floor() as i64;
+        if self.beat != Some(beat) {
+            self.beat = Some(beat);
+            self.active = self.rng.next_f32() < probability;
+            self.frame = 0;
+        }
+        if !self.active {
+            return StutterFrame::Off;
+        }
+
+        let division_samples = division.beats() * 60_f64 / tempo * sample_rate as f64;
+        let loop_frames = ((division_samples / block_size as f64).round() as usize)
+            .clamp(1, MAX_STUTTER_SAMPLES / block_size);
+
+        let frame = self.frame;
+        self.frame += 1;
+        if frame < loop_frames {
+            StutterFrame::Record(frame)
+        } else {
+            StutterFrame::Play((frame - loop_frames) % loop_frames)
+        }
+    }
+
+    /// Stops the current loop, the recorded frames do not match a new block size
+    pub fn stop(&mut self) {
+        self.active = false;
+    }
+}
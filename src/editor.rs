@@ -2,11 +2,16 @@ use nih_plug::prelude::ParamSetter;
 
 use plugin_utils::egui_utils::*;
 
+mod curve;
+use curve::curve_editor;
+
 mod style;
 use style::*;
 
 use crate::CrunchyParams;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use image::EncodableLayout;
@@ -26,11 +31,15 @@ fn load_images(cx: &egui::Context) -> HashMap<&'static str, egui::TextureHandle>
 
 pub(crate) struct UserState {
     pub(crate) handles: Option<HashMap<&'static str, egui::TextureHandle>>,
+    pub(crate) show_curve: bool,
 }
 
 impl Default for UserState {
     fn default() -> Self {
-        Self { handles: None }
+        Self {
+            handles: None,
+            show_curve: false,
+        }
     }
 }
 
@@ -75,7 +84,15 @@ fn knob_container(ui: &mut egui::Ui, params: Arc<CrunchyParams>, setter: &ParamS
     });
     ui.add_space(KNOB_PRESET.radius.unwrap_or(0_f32) * 0.25);
     ui.horizontal(|ui| {
-        ui.add_space(ui.available_width() - SPACE_RIGHT_OF_KNOBS - KNOB_WIDTH * 2_f32);
+        ui.add_space(ui.available_width() - SPACE_RIGHT_OF_KNOBS - KNOB_WIDTH * 3_f32);
+        ui.add(
+            ArcKnob::for_param(&params.block_size, setter, 0_f32, KnobLayout::Vertical)
+                .apply_preset(&KNOB_PRESET)
+                .set_hover_text(
+                    "Size of the transform blocks. Small blocks scream, large blocks smear"
+                        .to_string(),
+                ),
+        );
         ui.add(
             ArcKnob::for_param(&params.mix, setter, 0_f32, KnobLayout::Vertical)
                 .apply_preset(&KNOB_PRESET)
@@ -117,9 +134,44 @@ fn title_card(ui: &mut egui::Ui) {
 }
 
 const AUTHOR_FONT_SIZE: f32 = 12_f32;
-fn author_text(ui: &mut egui::Ui) {
+fn text_button(ui: &mut egui::Ui, text: &str, selected: bool, hover_text: &str) -> bool {
+    ui.add_sized(
+        egui::Vec2::new(WIDTH as f32 * 0.2_f32, HEIGHT as f32 * 0.05_f32),
+        egui::Button::new(
+            egui::RichText::new(text)
+                .size(AUTHOR_FONT_SIZE)
+                .color(FERRA_BLUSH),
+        )
+        .selected(selected)
+        .fill(FERRA_ASH.linear_multiply(BACKGROUND_OPACITY)),
+    )
+    .on_hover_text(hover_text)
+    .clicked()
+}
+
+fn author_text(ui: &mut egui::Ui, params: &CrunchyParams, curve_toggled: &Cell<bool>) {
     ui.horizontal(|ui| {
-        ui.add_space(WIDTH as f32 * 0.7_f32 - SPACE_RIGHT_OF_KNOBS - 15_f32);
+        ui.add_space(SPACE_RIGHT_OF_KNOBS);
+        if text_button(ui, "Curve", false, "Edit the crunch threshold curve") {
+            curve_toggled.set(true);
+        }
+        let bit_display = params.bit_display.load(Ordering::Relaxed);
+        if text_button(
+            ui,
+            "Bits",
+            bit_display,
+            "Show crush as a bit depth and crunch as a level in dBFS",
+        ) {
+            params.bit_display.store(!bit_display, Ordering::Relaxed);
+        }
+
+        ui.add_space(
+            WIDTH as f32 * 0.7_f32
+                - SPACE_RIGHT_OF_KNOBS
+                - 15_f32
+                - ui.min_rect().width()
+                - ui.spacing().item_spacing.x,
+        );
 
         let rect = ui
             .allocate_space(egui::Vec2::new(
@@ -169,6 +221,7 @@ pub(crate) fn create(params: Arc<CrunchyParams>, state: Arc<EguiState>) -> Optio
             cx.set_fonts(fonts);
         },
         move |cx, setter, user_state| {
+            let curve_toggled = Cell::new(false);
             CentralPanel::default()
                 .frame(egui::Frame::NONE)
                 .show(cx, |ui| {
@@ -178,10 +231,19 @@ pub(crate) fn create(params: Arc<CrunchyParams>, state: Arc<EguiState>) -> Optio
                             ui.add_space(HEIGHT as f32 * 0.11_f32);
                             knob_container(ui, params.clone(), &setter);
                             ui.add_space(HEIGHT as f32 * 0.020_f32);
-                            author_text(ui);
+                            author_text(ui, &params, &curve_toggled);
                         });
                     });
                 });
+
+            if curve_toggled.get() {
+                user_state.show_curve = !user_state.show_curve;
+            }
+            egui::Window::new("Crunch curve")
+                .open(&mut user_state.show_curve)
+                .collapsible(false)
+                .resizable(false)
+                .show(cx, |ui| curve_editor(ui, &params.crunch_curve));
         },
     )
 }
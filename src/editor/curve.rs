@@ -0,0 +1,139 @@
+use super::style::*;
+
+use crate::dsp::CURVE_MAX_TILT_DB;
+use crate::dsp::CURVE_POINTS;
+use crate::dsp::CURVE_RANGE_DB;
+use crate::CrunchCurve;
+use crate::CurveMode;
+use std::sync::RwLock;
+
+use nih_plug_egui::egui;
+
+const CURVE_RESOLUTION: usize = 128;
+const CURVE_GRID_HZ: [f32; 3] = [100_f32, 1_000_f32, 10_000_f32];
+
+/// Editor for the crunch threshold curve. The curve is edited on a copy, so the lock is only
+/// held for as long as it takes to write the changes back
+pub(crate) fn curve_editor(ui: &mut egui::Ui, curve: &RwLock<CrunchCurve>) {
+    let mut edited = match curve.read() {
+        Ok(v) => *v,
+        Err(_) => return,
+    };
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        changed |= ui
+            .radio_value(&mut edited.mode, CurveMode::Tilt, "Tilt")
+            .changed();
+        changed |= ui
+            .radio_value(&mut edited.mode, CurveMode::FreeHand, "Free hand")
+            .changed();
+        if ui.button("Reset").clicked() {
+            edited = CrunchCurve {
+                mode: edited.mode,
+                ..CrunchCurve::default()
+            };
+            changed = true;
+        }
+    });
+
+    if edited.mode == CurveMode::Tilt {
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut edited.tilt, -CURVE_MAX_TILT_DB..=CURVE_MAX_TILT_DB)
+                    .suffix(" dB/oct"),
+            )
+            .changed();
+    }
+
+    let (response, painter) = ui.allocate_painter(
+        egui::Vec2::new(CURVE_EDITOR_WIDTH, CURVE_EDITOR_HEIGHT),
+        egui::Sense::click_and_drag(),
+    );
+    let rect = response.rect;
+    let x_of = |position: f32| rect.left() + position * rect.width();
+    let y_of = |db: f32| rect.center().y - db / CURVE_RANGE_DB * rect.height() * 0.5_f32;
+    let db_of = |y: f32| {
+        ((rect.center().y - y) / (rect.height() * 0.5_f32) * CURVE_RANGE_DB)
+            .clamp(-CURVE_RANGE_DB, CURVE_RANGE_DB)
+    };
+    let index_of = |x: f32| {
+        (((x - rect.left()) / rect.width()).clamp(0_f32, 1_f32) * (CURVE_POINTS - 1) as f32).round()
+            as usize
+    };
+
+    // Free-hand drawing sets every breakpoint the pointer passed since the last frame
+    if edited.mode == CurveMode::FreeHand && (response.dragged() || response.clicked()) {
+        if let Some(pointer) = response.interact_pointer_pos() {
+            let previous = pointer - response.drag_delta();
+            let (from, to) = (index_of(previous.x), index_of(pointer.x));
+            let (from_db, to_db) = (db_of(previous.y), db_of(pointer.y));
+            for index in from.min(to)..=from.max(to) {
+                let fraction = if from == to {
+                    1_f32
+                } else {
+                    (index as f32 - from as f32) / (to as f32 - from as f32)
+                };
+                edited.points[index] = from_db + (to_db - from_db) * fraction;
+            }
+            changed = true;
+        }
+    }
+
+    painter.rect_filled(
+        rect,
+        egui::CornerRadius::from(BACKGROUND_ROUNDING),
+        FERRA_NIGHT,
+    );
+    let grid_stroke = egui::Stroke::new(1_f32, FERRA_UMBER);
+    for frequency in CURVE_GRID_HZ {
+        let x = x_of(CrunchCurve::frequency_to_position(frequency));
+        painter.line_segment(
+            [
+                egui::Pos2::new(x, rect.top()),
+                egui::Pos2::new(x, rect.bottom()),
+            ],
+            grid_stroke,
+        );
+    }
+    painter.line_segment(
+        [
+            egui::Pos2::new(rect.left(), y_of(0_f32)),
+            egui::Pos2::new(rect.right(), y_of(0_f32)),
+        ],
+        grid_stroke,
+    );
+
+    let line = (0..=CURVE_RESOLUTION)
+        .map(|i| {
+            let position = i as f32 / CURVE_RESOLUTION as f32;
+            let db = edited.gain_db_at(CrunchCurve::position_to_frequency(position));
+            egui::Pos2::new(x_of(position), y_of(db))
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        line,
+        egui::Stroke::new(2_f32, FERRA_ROSE),
+    ));
+
+    if edited.mode == CurveMode::FreeHand {
+        for (i, db) in edited.points.iter().enumerate() {
+            let position = i as f32 / (CURVE_POINTS - 1) as f32;
+            painter.circle_filled(
+                egui::Pos2::new(x_of(position), y_of(*db)),
+                2_f32,
+                FERRA_BLUSH,
+            );
+        }
+    }
+
+    response.on_hover_text(
+        "Crunch threshold per frequency. Raising the curve leaves that range cleaner",
+    );
+
+    if changed {
+        if let Ok(mut curve) = curve.write() {
+            *curve = edited;
+        }
+    }
+}
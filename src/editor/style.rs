@@ -34,6 +34,9 @@ pub(crate) const FERRA_HONEY: Color32 = Color32::from_rgb(245, 215, 110);
 pub(crate) const KNOB_WIDTH: f32 = KNOB_PRESET.radius.unwrap() * 2_f32 + 4.75_f32 + 16_f32;
 pub(crate) const SPACE_RIGHT_OF_KNOBS: f32 = WIDTH as f32 * 0.065_f32 + 1_f32;
 
+pub(crate) const CURVE_EDITOR_WIDTH: f32 = WIDTH as f32 * 0.8_f32;
+pub(crate) const CURVE_EDITOR_HEIGHT: f32 = HEIGHT as f32 * 0.3_f32;
+
 pub(crate) const BACKGROUND_ROUNDING: f32 = 8_f32;
 pub(crate) const BACKGROUND_OPACITY: f32 = 0.6_f32;
 
@@ -1,22 +1,37 @@
 use nih_plug::prelude::*;
 use nih_plug_egui::EguiState;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::RwLock;
 
+mod display;
 mod editor;
 
 mod dsp;
+pub use dsp::CrossSynthesis;
+pub use dsp::CrunchCurve;
+pub use dsp::CrunchShape;
+pub use dsp::CrunchyEngine;
 pub use dsp::CrunchyParamsBlock;
 pub use dsp::CrunchySingleChannelProcessor;
-pub use plugin_utils::dsp_utils::DspCoreProcessor;
+pub use dsp::CrushMode;
+pub use dsp::CrushNormalization;
+pub use dsp::CurveMode;
+pub use dsp::DropoutConcealment;
+pub use dsp::DropoutMode;
+pub use dsp::EnvelopeSource;
+pub use dsp::StutterDivision;
+pub use dsp::WindowShape;
 
 // TODO
 // [ ] - Rethink names of the effects
 
-const BLOCK_SIZE: usize = 64;
-
 struct Crunchy {
     params: Arc<CrunchyParams>,
-    dsp: Option<DspCoreProcessor<CrunchySingleChannelProcessor>>,
+    dsp: Option<CrunchyEngine>,
+    latency_samples: u32,
 }
 
 impl Default for Crunchy {
@@ -26,6 +41,7 @@ impl Default for Crunchy {
         Self {
             params: params.clone(),
             dsp: None,
+            latency_samples: 0,
         }
     }
 }
@@ -34,6 +50,11 @@ impl Default for Crunchy {
 pub struct CrunchyParams {
     #[persist = "editor-state"]
     editor_state: Arc<EguiState>,
+    #[persist = "crunch-curve"]
+    pub crunch_curve: Arc<RwLock<CrunchCurve>>,
+    /// Shows crush as a bit depth and crunch as a level in dBFS instead of percentages
+    #[persist = "bit-display"]
+    pub bit_display: Arc<AtomicBool>,
 
     #[id = "drive"]
     pub drive: FloatParam,
@@ -41,16 +62,106 @@ pub struct CrunchyParams {
     pub crunch: FloatParam,
     #[id = "crush"]
     pub crush: FloatParam,
+    #[id = "crunch_asymmetry"]
+    pub crunch_asymmetry: FloatParam,
+    #[id = "crunch_shape"]
+    pub crunch_shape: EnumParam<CrunchShape>,
+    #[id = "crush_mode"]
+    pub crush_mode: EnumParam<CrushMode>,
+    #[id = "crush_normalization"]
+    pub crush_normalization: EnumParam<CrushNormalization>,
+    #[id = "noise_fill"]
+    pub noise_fill: FloatParam,
+    #[id = "bitrate"]
+    pub bitrate: FloatParam,
+    #[id = "sparse_fraction"]
+    pub sparse_fraction: FloatParam,
+    #[id = "sparse_threshold"]
+    pub sparse_threshold: FloatParam,
+    #[id = "freeze"]
+    pub freeze: BoolParam,
+    #[id = "stutter_division"]
+    pub stutter_division: EnumParam<StutterDivision>,
+    #[id = "stutter_probability"]
+    pub stutter_probability: FloatParam,
+    #[id = "dropout_mode"]
+    pub dropout_mode: EnumParam<DropoutMode>,
+    #[id = "dropout_concealment"]
+    pub dropout_concealment: EnumParam<DropoutConcealment>,
+    #[id = "dropout_rate"]
+    pub dropout_rate: FloatParam,
+    /// Seed of every random process, so renders can be reproduced
+    #[id = "seed"]
+    pub seed: IntParam,
+    #[id = "shift"]
+    pub shift: IntParam,
+    #[id = "scramble"]
+    pub scramble: FloatParam,
+    #[id = "crunch_low"]
+    pub crunch_low: FloatParam,
+    #[id = "crunch_high"]
+    pub crunch_high: FloatParam,
+    #[id = "crush_low"]
+    pub crush_low: FloatParam,
+    #[id = "crush_high"]
+    pub crush_high: FloatParam,
+    #[id = "scramble_low"]
+    pub scramble_low: FloatParam,
+    #[id = "scramble_high"]
+    pub scramble_high: FloatParam,
+    #[id = "blur"]
+    pub blur: FloatParam,
+    #[id = "sbr_crossover"]
+    pub sbr_crossover: FloatParam,
+    #[id = "sbr_gain"]
+    pub sbr_gain: FloatParam,
+    /// Replaces the fitted gain compensation of crunch and crush with a measured one
+    #[id = "auto_gain"]
+    pub auto_gain: BoolParam,
+    #[id = "auto_gain_time"]
+    pub auto_gain_time: FloatParam,
+    #[id = "auto_gain_ceiling"]
+    pub auto_gain_ceiling: FloatParam,
+    #[id = "envelope_source"]
+    pub envelope_source: EnumParam<EnvelopeSource>,
+    /// Replaces the output with the sidechain input, delayed by the latency of the processing
+    #[id = "sidechain_listen"]
+    pub sidechain_listen: BoolParam,
+    #[id = "cross_synthesis"]
+    pub cross_synthesis: EnumParam<CrossSynthesis>,
+    #[id = "cross_synthesis_amount"]
+    pub cross_synthesis_amount: FloatParam,
+    #[id = "envelope_attack"]
+    pub envelope_attack: FloatParam,
+    #[id = "envelope_release"]
+    pub envelope_release: FloatParam,
+    #[id = "envelope_crunch"]
+    pub envelope_crunch: FloatParam,
+    #[id = "envelope_crush"]
+    pub envelope_crush: FloatParam,
+    #[id = "envelope_mix"]
+    pub envelope_mix: FloatParam,
     #[id = "mix"]
     pub mix: FloatParam,
     #[id = "gain"]
     pub gain: FloatParam,
+    #[id = "block_size"]
+    pub block_size: IntParam,
+    #[id = "window"]
+    pub window: EnumParam<WindowShape>,
+    #[id = "window_alpha"]
+    pub window_alpha: FloatParam,
 }
 
 impl Default for CrunchyParams {
     fn default() -> Self {
+        let bit_display = Arc::new(AtomicBool::new(false));
+        // The bit display of crush depends on the crush mode
+        let crush_mode = Arc::new(AtomicUsize::new(CrushMode::Uniform.to_index()));
+
         Self {
             editor_state: editor::default_state(),
+            crunch_curve: Arc::new(RwLock::new(CrunchCurve::default())),
 
             drive: FloatParam::new(
                 "Drive",
@@ -75,9 +186,8 @@ impl Default for CrunchyParams {
                 },
             )
             .with_smoother(SmoothingStyle::Linear(50_f32))
-            .with_unit(" %")
-            .with_value_to_string(formatters::v2s_f32_percentage(2))
-            .with_string_to_value(formatters::s2v_f32_percentage()),
+            .with_value_to_string(display::v2s_crunch(bit_display.clone()))
+            .with_string_to_value(display::s2v_crunch()),
 
             crush: FloatParam::new(
                 "Crush",
@@ -88,6 +198,310 @@ impl Default for CrunchyParams {
                 },
             )
             .with_smoother(SmoothingStyle::Linear(50_f32))
+            .with_value_to_string(display::v2s_crush(bit_display.clone(), crush_mode.clone()))
+            .with_string_to_value(display::s2v_crush(crush_mode.clone())),
+            crunch_asymmetry: FloatParam::new(
+                "Crunch asymmetry",
+                0_f32,
+                FloatRange::Linear {
+                    min: -1_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50_f32))
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            crunch_shape: EnumParam::new("Crunch shape", CrunchShape::Hard),
+            crush_mode: EnumParam::new("Crush mode", CrushMode::Uniform).with_callback(Arc::new(
+                move |mode: CrushMode| crush_mode.store(mode.to_index(), Ordering::Relaxed),
+            )),
+            crush_normalization: EnumParam::new("Crush normalization", CrushNormalization::Off),
+            noise_fill: FloatParam::new(
+                "Noise fill",
+                0_f32,
+                FloatRange::Linear {
+                    min: 0_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50_f32))
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            bitrate: FloatParam::new(
+                "Bitrate",
+                dsp::DEFAULT_BITRATE_KBPS,
+                FloatRange::Skewed {
+                    min: dsp::MIN_BITRATE_KBPS,
+                    max: dsp::MAX_BITRATE_KBPS,
+                    factor: FloatRange::skew_factor(-1_f32),
+                },
+            )
+            .with_unit(" kbps")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            sparse_fraction: FloatParam::new(
+                "Sparse fraction",
+                1_f32,
+                FloatRange::Skewed {
+                    min: 0_f32,
+                    max: 1_f32,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            sparse_threshold: FloatParam::new(
+                "Sparse threshold",
+                util::db_to_gain(dsp::MIN_SPARSE_THRESHOLD_DB),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(dsp::MIN_SPARSE_THRESHOLD_DB),
+                    max: util::db_to_gain(0.0),
+                    factor: FloatRange::gain_skew_factor(dsp::MIN_SPARSE_THRESHOLD_DB, 0.0),
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            freeze: BoolParam::new("Freeze", false),
+            stutter_division: EnumParam::new("Stutter division", StutterDivision::Sixteenth),
+            stutter_probability: FloatParam::new(
+                "Stutter probability",
+                0_f32,
+                FloatRange::Linear {
+                    min: 0_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            dropout_mode: EnumParam::new("Dropout mode", DropoutMode::Frame),
+            dropout_concealment: EnumParam::new("Dropout concealment", DropoutConcealment::Mute),
+            dropout_rate: FloatParam::new(
+                "Dropout rate",
+                0_f32,
+                FloatRange::Linear {
+                    min: 0_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            seed: IntParam::new("Seed", 0, IntRange::Linear { min: 0, max: 9999 }),
+            shift: IntParam::new(
+                "Shift",
+                0,
+                IntRange::Linear {
+                    min: -dsp::MAX_SHIFT_BINS,
+                    max: dsp::MAX_SHIFT_BINS,
+                },
+            )
+            .with_unit(" bins"),
+            scramble: FloatParam::new(
+                "Scramble",
+                0_f32,
+                FloatRange::Linear {
+                    min: 0_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            crunch_low: FloatParam::new(
+                "Crunch low",
+                dsp::MIN_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: dsp::MIN_CUTOFF_HZ,
+                    max: dsp::MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            crunch_high: FloatParam::new(
+                "Crunch high",
+                dsp::MAX_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: dsp::MIN_CUTOFF_HZ,
+                    max: dsp::MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            crush_low: FloatParam::new(
+                "Crush low",
+                dsp::MIN_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: dsp::MIN_CUTOFF_HZ,
+                    max: dsp::MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            crush_high: FloatParam::new(
+                "Crush high",
+                dsp::MAX_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: dsp::MIN_CUTOFF_HZ,
+                    max: dsp::MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            scramble_low: FloatParam::new(
+                "Scramble low",
+                dsp::MIN_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: dsp::MIN_CUTOFF_HZ,
+                    max: dsp::MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            scramble_high: FloatParam::new(
+                "Scramble high",
+                dsp::MAX_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: dsp::MIN_CUTOFF_HZ,
+                    max: dsp::MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            blur: FloatParam::new(
+                "Blur",
+                0_f32,
+                FloatRange::Skewed {
+                    min: 0_f32,
+                    max: dsp::MAX_BLUR_MS,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50_f32))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Left at the top of the range, replication is disabled
+            sbr_crossover: FloatParam::new(
+                "SBR crossover",
+                dsp::MAX_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: dsp::MIN_CUTOFF_HZ,
+                    max: dsp::MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(2))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            sbr_gain: FloatParam::new(
+                "Replication gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(12.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 12.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            auto_gain: BoolParam::new("Auto gain", false),
+            auto_gain_time: FloatParam::new(
+                "Auto gain time",
+                300_f32,
+                FloatRange::Skewed {
+                    min: dsp::MIN_AUTO_GAIN_MS,
+                    max: dsp::MAX_AUTO_GAIN_MS,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            auto_gain_ceiling: FloatParam::new(
+                "Auto gain ceiling",
+                12_f32,
+                FloatRange::Linear {
+                    min: 0_f32,
+                    max: dsp::MAX_AUTO_GAIN_CEILING_DB,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            envelope_source: EnumParam::new("Envelope source", EnvelopeSource::Input),
+            sidechain_listen: BoolParam::new("Sidechain listen", false),
+            cross_synthesis: EnumParam::new("Cross-synthesis", CrossSynthesis::Off),
+            cross_synthesis_amount: FloatParam::new(
+                "Cross-synthesis amount",
+                1_f32,
+                FloatRange::Linear {
+                    min: 0_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            envelope_attack: FloatParam::new(
+                "Envelope attack",
+                5_f32,
+                FloatRange::Skewed {
+                    min: dsp::MIN_ENVELOPE_ATTACK_MS,
+                    max: dsp::MAX_ENVELOPE_ATTACK_MS,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            envelope_release: FloatParam::new(
+                "Envelope release",
+                150_f32,
+                FloatRange::Skewed {
+                    min: dsp::MIN_ENVELOPE_RELEASE_MS,
+                    max: dsp::MAX_ENVELOPE_RELEASE_MS,
+                    factor: FloatRange::skew_factor(-2_f32),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            envelope_crunch: FloatParam::new(
+                "Envelope to crunch",
+                0_f32,
+                FloatRange::Linear {
+                    min: -1_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            envelope_crush: FloatParam::new(
+                "Envelope to crush",
+                0_f32,
+                FloatRange::Linear {
+                    min: -1_f32,
+                    max: 1_f32,
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            envelope_mix: FloatParam::new(
+                "Envelope to mix",
+                0_f32,
+                FloatRange::Linear {
+                    min: -1_f32,
+                    max: 1_f32,
+                },
+            )
             .with_unit(" %")
             .with_value_to_string(formatters::v2s_f32_percentage(2))
             .with_string_to_value(formatters::s2v_f32_percentage()),
@@ -116,6 +530,38 @@ impl Default for CrunchyParams {
             .with_unit(" dB")
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            block_size: IntParam::new(
+                "Block size",
+                dsp::DEFAULT_BLOCK_SIZE_LOG2,
+                IntRange::Linear {
+                    min: dsp::MIN_BLOCK_SIZE_LOG2,
+                    max: dsp::MAX_BLOCK_SIZE_LOG2,
+                },
+            )
+            .with_unit(" samples")
+            .with_value_to_string(Arc::new(|value| {
+                dsp::block_size_from_log2(value).to_string()
+            }))
+            .with_string_to_value(Arc::new(|string| {
+                string
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|v| v.is_power_of_two())
+                    .map(|v| v.trailing_zeros() as i32)
+            })),
+            window: EnumParam::new("Window", WindowShape::Sine),
+            window_alpha: FloatParam::new(
+                "Window alpha",
+                dsp::DEFAULT_WINDOW_ALPHA,
+                FloatRange::Linear {
+                    min: 0_f32,
+                    max: 10_f32,
+                },
+            )
+            .with_step_size(0.01_f32)
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            bit_display,
         }
     }
 }
@@ -129,6 +575,27 @@ impl Plugin for Crunchy {
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
     const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[new_nonzero_u32(2)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            aux_input_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        // Fallbacks for hosts that do not support aux ports, the sidechain is silent then
         AudioIOLayout {
             main_input_channels: NonZeroU32::new(2),
             main_output_channels: NonZeroU32::new(2),
@@ -141,6 +608,7 @@ impl Plugin for Crunchy {
         },
     ];
 
+    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
@@ -157,30 +625,65 @@ impl Plugin for Crunchy {
     fn initialize(
         &mut self,
         audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
-        self.dsp = Some(DspCoreProcessor::new(
+        let dsp = CrunchyEngine::new(
             self.params.clone(),
-            BLOCK_SIZE,
             match audio_io_layout.main_input_channels {
                 Some(v) => v.get() as usize,
                 None => {
                     return false;
                 }
             },
-        ));
+            buffer_config.sample_rate,
+        );
+
+        // Both the block buffering and the MDCT overlap delay the signal, the host needs to
+        // know about it to keep plugin delay compensation in sync
+        self.latency_samples = dsp.latency_samples();
+        context.set_latency_samples(self.latency_samples);
+
+        self.dsp = Some(dsp);
         true
     }
 
+    fn reset(&mut self) {
+        if let Some(algo) = &mut self.dsp {
+            algo.reset();
+        }
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
         if let Some(algo) = &mut self.dsp {
-            algo.process(buffer)
+            // Any held note freezes the spectrum
+            while let Some(event) = context.next_event() {
+                match event {
+                    NoteEvent::NoteOn { .. } => algo.note_on(),
+                    NoteEvent::NoteOff { .. } => algo.note_off(),
+                    _ => (),
+                }
+            }
+
+            let sidechain: &[&mut [f32]] = match aux.inputs.first_mut() {
+                Some(v) => v.as_slice(),
+                None => &[],
+            };
+            let status = algo.process(buffer, sidechain, context.transport());
+
+            // Block size changes are applied by the engine, report the new latency afterwards
+            let latency_samples = algo.latency_samples();
+            if latency_samples != self.latency_samples {
+                self.latency_samples = latency_samples;
+                context.set_latency_samples(latency_samples);
+            }
+
+            status
         } else {
             ProcessStatus::Error("DSP data not initialized")
         }